@@ -0,0 +1,65 @@
+use israeli_queue_etc::{AbstractPriorityQueue, BucketQueue};
+use priority_queue::PriorityQueue;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// the system allocator but counting how many allocations are made
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+type Bucket = PriorityQueue<usize, usize>;
+
+/// a burst of work in one bucket which is then all dequeued
+/// and then the next burst goes in a different bucket
+/// so every burst needs a bucket again
+fn oscillating_workload(bucket_pool_limit: usize) -> (usize, Duration) {
+    let mut q = BucketQueue::<usize, usize, usize, Bucket, HashMap<usize, Bucket>>::new(
+        0,
+        0,
+        &Bucket::new(),
+    );
+    q.change_bucket_pool_limit(bucket_pool_limit);
+    let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for round in 0..20_000 {
+        let which_bucket = round % 7;
+        for idx in 0..50 {
+            q.my_enqueue(idx, which_bucket);
+        }
+        let _ = q.dequeue_batch(50, 50);
+    }
+    let elapsed = start.elapsed();
+    (
+        ALLOCATIONS.load(Ordering::Relaxed) - allocations_before,
+        elapsed,
+    )
+}
+
+fn main() {
+    for bucket_pool_limit in [0, 1, 8] {
+        let (allocations, elapsed) = oscillating_workload(bucket_pool_limit);
+        println!("bucket pool limit {bucket_pool_limit}: {allocations} allocations in {elapsed:?}");
+    }
+}
@@ -0,0 +1,81 @@
+use israeli_queue_etc::{AbstractPriorityQueue, BlockingQueue, KeyedDependencies, ResourceKeys};
+use std::time::{Duration, Instant};
+
+/// a task that touches nothing so it is ready as soon as it is enqueued
+struct Independent;
+
+impl ResourceKeys for Independent {
+    type Key = ();
+
+    fn reads(&self) -> impl Iterator<Item = ()> + '_ {
+        std::iter::empty()
+    }
+
+    fn writes(&self) -> impl Iterator<Item = ()> + '_ {
+        std::iter::empty()
+    }
+}
+
+/// the ready set as it was before, a list scanned for the highest priority
+#[derive(Default)]
+struct LinearScan(Vec<(Independent, u64)>);
+
+impl LinearScan {
+    fn push(&mut self, new_obj: Independent, new_obj_priority: u64) {
+        self.0.push((new_obj, new_obj_priority));
+    }
+
+    fn pop(&mut self) -> Option<(Independent, u64)> {
+        let (which, _) = self.0.iter().enumerate().fold(
+            None,
+            |acc: Option<(usize, u64)>, (which, (_, w))| match acc {
+                Some((_, best)) if best >= *w => acc,
+                _ => Some((which, *w)),
+            },
+        )?;
+        Some(self.0.remove(which))
+    }
+}
+
+/// keep `ready` tasks ready while dequeueing one and enqueueing another
+fn ready_workload(
+    ready: usize,
+    mut enqueue: impl FnMut(u64),
+    mut dequeue: impl FnMut() -> Option<u64>,
+) -> Duration {
+    let mut step = 17u64;
+    let mut next_priority = || {
+        step = step.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+        step >> 40
+    };
+    for _ in 0..ready {
+        enqueue(next_priority());
+    }
+    let start = Instant::now();
+    for _ in 0..20_000 {
+        let _ = dequeue();
+        enqueue(next_priority());
+    }
+    start.elapsed()
+}
+
+fn main() {
+    for ready in [100, 1_000, 10_000] {
+        let mut heap = BlockingQueue::<Independent, u64, KeyedDependencies<()>>::default();
+        let heap_time = {
+            let heap = std::cell::RefCell::new(&mut heap);
+            ready_workload(
+                ready,
+                |w| heap.borrow_mut().my_enqueue(Independent, w),
+                || heap.borrow_mut().my_dequeue().map(|(_, w)| w),
+            )
+        };
+        let linear = std::cell::RefCell::new(LinearScan::default());
+        let linear_time = ready_workload(
+            ready,
+            |w| linear.borrow_mut().push(Independent, w),
+            || linear.borrow_mut().pop().map(|(_, w)| w),
+        );
+        println!("{ready} ready: indexed heap {heap_time:?}, linear scan {linear_time:?}");
+    }
+}
@@ -0,0 +1,47 @@
+use israeli_queue_etc::{AbstractPriorityQueue, Friendly, IsraeliPriority};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+static SHIBBOLETH_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+/// a tenant whose shibboleth is expensive to compute
+struct Tenant {
+    record: Vec<u8>,
+}
+
+impl Friendly<u64> for Tenant {
+    fn friendship_shibboleth(&self) -> u64 {
+        SHIBBOLETH_CALLS.fetch_add(1, Ordering::Relaxed);
+        self.record.iter().fold(0xcbf2_9ce4_8422_2325, |acc, b| {
+            (acc ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+        }) % 97
+    }
+}
+
+fn main() {
+    let num_rounds = 2_000;
+    let mut q = IsraeliPriority::<Tenant, u32, u64>::with_capacity(128);
+    let mut num_enqueued = 0;
+    let start = Instant::now();
+    for round in 0..num_rounds {
+        let tenant = |which: u32| Tenant {
+            record: (which % 150).to_le_bytes().repeat(64),
+        };
+        q.enqueue_batch((0..4).map(|_| tenant(round)), round % 17);
+        q.enqueue_batch((0..4).map(|k| tenant(round + k)), round % 13);
+        q.my_enqueue(tenant(round * 7), round % 11);
+        num_enqueued += 9;
+        let _ = q.my_dequeue();
+        let _ = q.dequeue_batch(4, 6);
+    }
+    let _ = q.drain_all();
+    let elapsed = start.elapsed();
+    let calls = SHIBBOLETH_CALLS.load(Ordering::Relaxed);
+    println!(
+        "{num_enqueued} items enqueued, {calls} calls to friendship_shibboleth, {elapsed:?} elapsed"
+    );
+    assert_eq!(
+        calls, num_enqueued,
+        "every shibboleth computed exactly once"
+    );
+}
@@ -0,0 +1,114 @@
+use israeli_queue_etc::{
+    AbstractPriorityQueue, BucketQueue, HierarchicalBitmap, NoPriorityQueue, OccupancyIndex,
+};
+use std::cell::Cell;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+type Bucket = NoPriorityQueue<usize, usize>;
+
+/// how `BucketQueue` found the next bucket before it had an `OccupancyIndex`
+/// going down one coarse grained priority at a time from an upper bound
+/// the upper bound only goes back up when something is put above it
+/// and the lower bound only ever goes down
+struct DecrementWalk {
+    occupied: HashSet<usize>,
+    upper_bound: Cell<usize>,
+    lower_bound: usize,
+}
+
+impl OccupancyIndex<usize> for DecrementWalk {
+    fn new() -> Self {
+        Self {
+            occupied: HashSet::new(),
+            upper_bound: Cell::new(0),
+            lower_bound: 0,
+        }
+    }
+
+    fn occupy(&mut self, which: &usize) {
+        self.occupied.insert(*which);
+        self.upper_bound.set(self.upper_bound.get().max(*which));
+        self.lower_bound = self.lower_bound.min(*which);
+    }
+
+    fn vacate(&mut self, which: &usize) {
+        self.occupied.remove(which);
+    }
+
+    fn highest_occupied(&self) -> Option<usize> {
+        let mut looking_in_bucket = self.upper_bound.get();
+        while !self.occupied.contains(&looking_in_bucket) {
+            if looking_in_bucket == self.lower_bound {
+                self.upper_bound.set(looking_in_bucket);
+                return None;
+            }
+            looking_in_bucket -= 1;
+        }
+        self.upper_bound.set(looking_in_bucket);
+        Some(looking_in_bucket)
+    }
+
+    fn lowest_occupied(&self) -> Option<usize> {
+        (self.lower_bound..=self.upper_bound.get()).find(|z| self.occupied.contains(z))
+    }
+
+    fn all_occupied(&self) -> impl Iterator<Item = usize> + '_ {
+        (self.lower_bound..=self.upper_bound.get()).filter(|z| self.occupied.contains(z))
+    }
+}
+
+/// a few items spread out over a wide range of buckets
+/// interleaving enqueues and dequeues like a simulation would
+fn sparse_workload<Q: AbstractPriorityQueue<usize, usize>>(
+    mut q: Q,
+    spread: usize,
+    rounds: usize,
+) -> Duration {
+    let mut step = 17usize;
+    let start = Instant::now();
+    for _ in 0..rounds {
+        for _ in 0..3 {
+            step = step.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            let priority = (step >> 20) % spread;
+            q.my_enqueue(priority, priority);
+        }
+        let _ = q.my_dequeue();
+        let _ = q.dequeue_batch(2, 2);
+    }
+    let _ = q.drain_all();
+    start.elapsed()
+}
+
+fn with_occupancy<Occupied: OccupancyIndex<usize>>(
+) -> BucketQueue<usize, usize, usize, Bucket, HashMap<usize, Bucket>, Occupied> {
+    BucketQueue::new(0, 0, &Bucket::new())
+}
+
+/// the decrement walk goes over every empty bucket between occupied ones
+/// so it only gets as many rounds as finish in reasonable time
+/// the hierarchical bitmap needs a bit for every bucket up to the highest one ever used
+/// at the widest spread that is 8 MiB of bits for only a handful of occupied buckets
+/// which all has to be zeroed as it grows, and the words it touches on the way down
+/// are spread out over all of that so they are rarely in cache
+/// the ordered set only ever holds the handful of occupied buckets, so it is faster there
+fn main() {
+    for (spread, rounds, walk_rounds) in [
+        (1 << 10, 20_000, Some(20_000)),
+        (1 << 16, 20_000, Some(200)),
+        (1 << 20, 20_000, Some(20)),
+        (1 << 26, 20_000, None),
+    ] {
+        let walked = walk_rounds.map_or("too slow".to_string(), |walk_rounds| {
+            format!(
+                "{:?} for {walk_rounds} rounds",
+                sparse_workload(with_occupancy::<DecrementWalk>(), spread, walk_rounds)
+            )
+        });
+        println!(
+            "spread {spread}, {rounds} rounds: ordered set {:?}, hierarchical bitmap {:?}, decrement walk {walked}",
+            sparse_workload(with_occupancy::<BTreeSet<usize>>(), spread, rounds),
+            sparse_workload(with_occupancy::<HierarchicalBitmap>(), spread, rounds),
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use crate::my_priority_queue::AbstractPriorityQueue;
+
+/// the part of `AbstractPriorityQueue` that can be used through a trait object
+/// every `AbstractPriorityQueue` is one
+pub trait DynPriorityQueue<T, P: Ord> {
+    fn dyn_empty_copy(&self) -> Box<dyn DynPriorityQueue<T, P>>;
+    fn dyn_peek(&self) -> Option<(&T, &P)>;
+    fn dyn_enqueue(&mut self, new_obj: T, new_obj_priority: P);
+    fn dyn_enqueue_batch(&mut self, new_batch: Vec<T>, new_batch_priority: P);
+    fn dyn_dequeue(&mut self) -> Option<(T, P)>;
+    fn dyn_dequeue_batch(&mut self, around_how_many: usize, hard_limit: usize) -> Vec<(T, P)>;
+    fn dyn_len(&self) -> usize;
+    fn dyn_is_empty(&self) -> bool;
+}
+
+impl<T, P, Q> DynPriorityQueue<T, P> for Q
+where
+    P: Ord,
+    Q: AbstractPriorityQueue<T, P> + 'static,
+{
+    fn dyn_empty_copy(&self) -> Box<dyn DynPriorityQueue<T, P>> {
+        Box::new(self.empty_copy())
+    }
+
+    fn dyn_peek(&self) -> Option<(&T, &P)> {
+        self.my_peek()
+    }
+
+    fn dyn_enqueue(&mut self, new_obj: T, new_obj_priority: P) {
+        self.my_enqueue(new_obj, new_obj_priority);
+    }
+
+    fn dyn_enqueue_batch(&mut self, new_batch: Vec<T>, new_batch_priority: P) {
+        self.enqueue_batch(new_batch, new_batch_priority);
+    }
+
+    fn dyn_dequeue(&mut self) -> Option<(T, P)> {
+        self.my_dequeue()
+    }
+
+    fn dyn_dequeue_batch(&mut self, around_how_many: usize, hard_limit: usize) -> Vec<(T, P)> {
+        self.dequeue_batch(around_how_many, hard_limit)
+    }
+
+    fn dyn_len(&self) -> usize {
+        self.my_len()
+    }
+
+    fn dyn_is_empty(&self) -> bool {
+        self.is_empty()
+    }
+}
+
+/// any kind of `AbstractPriorityQueue` for these items and priorities
+/// chosen at runtime, like from a configuration
+/// this is itself an `AbstractPriorityQueue` so it can be the buckets of a `BucketQueue`
+pub struct BoxedQueue<T, P: Ord>(Box<dyn DynPriorityQueue<T, P>>);
+
+impl<T, P: Ord> BoxedQueue<T, P> {
+    pub fn new(underlying: impl AbstractPriorityQueue<T, P> + 'static) -> Self {
+        Self(Box::new(underlying))
+    }
+}
+
+impl<T, P: Ord> AbstractPriorityQueue<T, P> for BoxedQueue<T, P> {
+    fn empty_copy(&self) -> Self {
+        Self(self.0.dyn_empty_copy())
+    }
+
+    fn my_peek(&self) -> Option<(&T, &P)> {
+        self.0.dyn_peek()
+    }
+
+    fn my_enqueue(&mut self, new_obj: T, new_obj_priority: P) {
+        self.0.dyn_enqueue(new_obj, new_obj_priority);
+    }
+
+    fn enqueue_batch(&mut self, new_batch: impl IntoIterator<Item = T>, new_batch_priority: P) {
+        self.0
+            .dyn_enqueue_batch(new_batch.into_iter().collect(), new_batch_priority);
+    }
+
+    fn my_dequeue(&mut self) -> Option<(T, P)> {
+        self.0.dyn_dequeue()
+    }
+
+    fn dequeue_batch(&mut self, around_how_many: usize, hard_limit: usize) -> Vec<(T, P)> {
+        self.0.dyn_dequeue_batch(around_how_many, hard_limit)
+    }
+
+    fn my_len(&self) -> usize {
+        self.0.dyn_len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.dyn_is_empty()
+    }
+}
+
+mod test {
+
+    #[test]
+    fn same_as_unboxed() {
+        use super::BoxedQueue;
+        use crate::my_priority_queue::AbstractPriorityQueue;
+        use crate::NoPriorityQueue;
+        use priority_queue::PriorityQueue;
+
+        let mut q = BoxedQueue::new(PriorityQueue::<u8, u8>::new());
+        q.enqueue_batch([1, 2], 5);
+        q.my_enqueue(3, 9);
+        assert_eq!(q.my_len(), 3);
+        assert_eq!(q.my_peek(), Some((&3, &9)));
+        let mut q2 = q.empty_copy();
+        assert!(q2.is_empty());
+        assert_eq!(q.my_dequeue(), Some((3, 9)));
+        assert_eq!(q.drain_all().len(), 2);
+        q2.my_enqueue(4, 0);
+        assert_eq!(q2.my_len(), 1);
+
+        let mut q3 = BoxedQueue::new(NoPriorityQueue::<u8, u8>::new());
+        q3.enqueue_batch([1, 2], 5);
+        q3.my_enqueue(3, 9);
+        assert_eq!(q3.dequeue_batch(3, 3), vec![(1, 0), (2, 0), (3, 0)]);
+    }
+}
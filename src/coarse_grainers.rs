@@ -0,0 +1,412 @@
+use std::{
+    cmp::Reverse,
+    time::{Duration, Instant},
+};
+
+use crate::nested_queue::{CoarseGrainedPriority, CoarseGrainer};
+
+/// keep only the top `K` bits of an integer priority
+/// so there are at most `2^K` buckets
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct TopBits<P, const K: u32>(pub P);
+
+/// integer priorities in buckets of `WIDTH` consecutive values
+/// rounding down, so negative priorities work too
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct DivideBy<P, const WIDTH: u64>(pub P);
+
+/// integer priorities bucketed by how many bits they need
+/// so each bucket is twice as wide as the one below it
+/// negative priorities are mirrored to negative buckets
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct Log2Bucket(pub i32);
+
+/// `Duration` priorities in buckets `NANOS_PER_BUCKET` nanoseconds wide
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct DurationBucket<const NANOS_PER_BUCKET: u64>(pub u64);
+
+/// `Instant` priorities in buckets `NANOS_PER_BUCKET` nanoseconds wide
+/// measured from the epoch of an `InstantGrainer`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct InstantBucket<const NANOS_PER_BUCKET: u64>(pub i64);
+
+/// deadlines as `Reverse<Instant>` or `Reverse<Duration>` priorities
+/// so the earliest deadline is the highest priority
+/// in buckets `NANOS_PER_BUCKET` nanoseconds wide
+/// the `Instant` ones measured from the epoch of an `InstantGrainer`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct DeadlineBucket<const NANOS_PER_BUCKET: u64>(pub i64);
+
+/// coarse grains `Instant` priorities into `InstantBucket` or `DeadlineBucket`
+/// counting buckets from its own epoch
+/// so two queues with different epochs don't interfere
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct InstantGrainer {
+    epoch: Instant,
+}
+
+impl InstantGrainer {
+    /// bucket 0 starts at `epoch`
+    #[must_use]
+    pub fn new(epoch: Instant) -> Self {
+        Self { epoch }
+    }
+
+    /// where the buckets are counted from
+    #[must_use]
+    pub fn epoch(&self) -> Instant {
+        self.epoch
+    }
+}
+
+impl Default for InstantGrainer {
+    /// the epoch is now
+    fn default() -> Self {
+        Self::new(Instant::now())
+    }
+}
+
+/// the coarse grainings that work for every integer type
+/// including the identity where every priority is its own bucket
+macro_rules! integer_coarse_grainers {
+    ($($t:ty),*) => {
+        $(
+            impl CoarseGrainedPriority<$t> for $t {
+                fn coarse_grain(p: &$t) -> Self {
+                    *p
+                }
+
+                fn decrement(&mut self) {
+                    *self = self.saturating_sub(1);
+                }
+            }
+
+            impl<const K: u32> CoarseGrainedPriority<$t> for TopBits<$t, K> {
+                fn coarse_grain(p: &$t) -> Self {
+                    const {
+                        assert!(K > 0 && K <= <$t>::BITS, "keeping between 1 and all of the bits")
+                    };
+                    Self(*p >> (<$t>::BITS - K))
+                }
+
+                fn decrement(&mut self) {
+                    self.0 = self.0.saturating_sub(1);
+                }
+            }
+
+            impl<const WIDTH: u64> CoarseGrainedPriority<$t> for DivideBy<$t, WIDTH> {
+                #[allow(unused_comparisons, clippy::absurd_extreme_comparisons)]
+                fn coarse_grain(p: &$t) -> Self {
+                    const { assert!(WIDTH > 0, "buckets have positive width") };
+                    <$t>::try_from(WIDTH).map_or_else(
+                        |_| {
+                            // a width too big for the type puts everything negative in bucket -1
+                            // and everything nonnegative in bucket 0
+                            if *p < 0 {
+                                Self((0 as $t).saturating_sub(1))
+                            } else {
+                                Self(0)
+                            }
+                        },
+                        |width| Self(p.div_euclid(width)),
+                    )
+                }
+
+                fn decrement(&mut self) {
+                    self.0 = self.0.saturating_sub(1);
+                }
+            }
+
+            impl CoarseGrainedPriority<$t> for Log2Bucket {
+                #[allow(unused_comparisons, clippy::absurd_extreme_comparisons)]
+                fn coarse_grain(p: &$t) -> Self {
+                    let num_bits = i32::try_from(<$t>::BITS - p.unsigned_abs().leading_zeros())
+                        .expect("at most 128 bits");
+                    if *p < 0 {
+                        Self(-num_bits)
+                    } else {
+                        Self(num_bits)
+                    }
+                }
+
+                fn decrement(&mut self) {
+                    self.0 = self.0.saturating_sub(1);
+                }
+            }
+        )*
+    };
+}
+
+/// `unsigned_abs` for the unsigned types so `Log2Bucket` can treat all of them the same
+trait UnsignedAbs {
+    fn unsigned_abs(self) -> Self;
+}
+
+macro_rules! unsigned_abs_identity {
+    ($($t:ty),*) => {
+        $(
+            impl UnsignedAbs for $t {
+                fn unsigned_abs(self) -> Self {
+                    self
+                }
+            }
+        )*
+    };
+}
+
+unsigned_abs_identity!(u8, u16, u32, u64, u128, usize);
+integer_coarse_grainers!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+/// how many buckets of `nanos_per_bucket` fit in `how_long`
+fn duration_buckets(how_long: Duration, nanos_per_bucket: u64) -> u64 {
+    u64::try_from(how_long.as_nanos() / u128::from(nanos_per_bucket)).unwrap_or(u64::MAX)
+}
+
+/// which bucket of `nanos_per_bucket` does `when` fall in
+/// counting from `epoch`
+fn instant_buckets(epoch: Instant, when: Instant, nanos_per_bucket: u64) -> i64 {
+    if let Some(after_epoch) = when.checked_duration_since(epoch) {
+        i64::try_from(duration_buckets(after_epoch, nanos_per_bucket)).unwrap_or(i64::MAX)
+    } else {
+        // rounding down means being partway into a bucket before the epoch
+        // puts you in the bucket below
+        let before_epoch = epoch.duration_since(when);
+        let full_buckets = duration_buckets(before_epoch, nanos_per_bucket);
+        let partial = !before_epoch
+            .as_nanos()
+            .is_multiple_of(u128::from(nanos_per_bucket));
+        let buckets_below = full_buckets.saturating_add(u64::from(partial));
+        i64::try_from(buckets_below).map_or(i64::MIN, |z| -z)
+    }
+}
+
+impl<const NANOS_PER_BUCKET: u64> CoarseGrainedPriority<Duration>
+    for DurationBucket<NANOS_PER_BUCKET>
+{
+    fn coarse_grain(p: &Duration) -> Self {
+        const { assert!(NANOS_PER_BUCKET > 0, "buckets have positive width") };
+        Self(duration_buckets(*p, NANOS_PER_BUCKET))
+    }
+
+    fn decrement(&mut self) {
+        self.0 = self.0.saturating_sub(1);
+    }
+}
+
+impl<const NANOS_PER_BUCKET: u64> CoarseGrainer<Instant, InstantBucket<NANOS_PER_BUCKET>>
+    for InstantGrainer
+{
+    fn coarse_grain(&self, p: &Instant) -> InstantBucket<NANOS_PER_BUCKET> {
+        const { assert!(NANOS_PER_BUCKET > 0, "buckets have positive width") };
+        InstantBucket(instant_buckets(self.epoch, *p, NANOS_PER_BUCKET))
+    }
+}
+
+impl<const NANOS_PER_BUCKET: u64> CoarseGrainer<Reverse<Instant>, DeadlineBucket<NANOS_PER_BUCKET>>
+    for InstantGrainer
+{
+    fn coarse_grain(&self, p: &Reverse<Instant>) -> DeadlineBucket<NANOS_PER_BUCKET> {
+        const { assert!(NANOS_PER_BUCKET > 0, "buckets have positive width") };
+        DeadlineBucket(instant_buckets(self.epoch, p.0, NANOS_PER_BUCKET).saturating_neg())
+    }
+}
+
+impl<const NANOS_PER_BUCKET: u64> CoarseGrainedPriority<Reverse<Duration>>
+    for DeadlineBucket<NANOS_PER_BUCKET>
+{
+    fn coarse_grain(p: &Reverse<Duration>) -> Self {
+        const { assert!(NANOS_PER_BUCKET > 0, "buckets have positive width") };
+        let buckets = duration_buckets(p.0, NANOS_PER_BUCKET);
+        Self(i64::try_from(buckets).map_or(i64::MIN, |z| -z))
+    }
+
+    fn decrement(&mut self) {
+        self.0 = self.0.saturating_sub(1);
+    }
+}
+
+mod test {
+    #[allow(dead_code)]
+    fn assert_grainer_monotone<P, C>(
+        grainer: &impl crate::nested_queue::CoarseGrainer<P, C>,
+        sorted_priorities: impl IntoIterator<Item = P>,
+    ) -> Vec<C>
+    where
+        P: Ord + std::fmt::Debug,
+        C: Ord + Clone + std::fmt::Debug,
+    {
+        let mut previous: Option<(P, C)> = None;
+        let mut grained = Vec::new();
+        for p in sorted_priorities {
+            let c = grainer.coarse_grain(&p);
+            if let Some((previous_p, previous_c)) = previous {
+                assert!(previous_p <= p, "{previous_p:?} then {p:?} were not sorted");
+                assert!(
+                    previous_c <= c,
+                    "{previous_p:?} <= {p:?} but {previous_c:?} > {c:?}"
+                );
+            }
+            grained.push(c.clone());
+            previous = Some((p, c));
+        }
+        grained
+    }
+
+    #[allow(dead_code)]
+    fn assert_monotone<P, C>(sorted_priorities: impl IntoIterator<Item = P>)
+    where
+        P: Ord + std::fmt::Debug,
+        C: crate::nested_queue::CoarseGrainedPriority<P> + Ord + Clone + std::fmt::Debug,
+    {
+        let grained = assert_grainer_monotone::<P, C>(
+            &crate::nested_queue::ByCoarseGrainedPriority,
+            sorted_priorities,
+        );
+        for c in grained {
+            let mut below = c.clone();
+            below.decrement();
+            assert!(below <= c);
+        }
+    }
+
+    #[test]
+    fn integers_monotone() {
+        use super::{DivideBy, Log2Bucket, TopBits};
+
+        assert_monotone::<u8, u8>(0..=u8::MAX);
+        assert_monotone::<i8, i8>(i8::MIN..=i8::MAX);
+        assert_monotone::<u8, TopBits<u8, 3>>(0..=u8::MAX);
+        assert_monotone::<i16, TopBits<i16, 5>>(i16::MIN..=i16::MAX);
+        assert_monotone::<u16, DivideBy<u16, 100>>(0..=u16::MAX);
+        assert_monotone::<i32, DivideBy<i32, 7>>(-1000..=1000);
+        assert_monotone::<i8, DivideBy<i8, 1000>>(i8::MIN..=i8::MAX);
+        assert_monotone::<u8, DivideBy<u8, 1000>>(0..=u8::MAX);
+        assert_monotone::<i128, DivideBy<i128, { u64::MAX }>>([i128::MIN, -1, 0, i128::MAX]);
+        assert_monotone::<u16, Log2Bucket>(0..=u16::MAX);
+        assert_monotone::<i16, Log2Bucket>(i16::MIN..=i16::MAX);
+        assert_monotone::<i64, Log2Bucket>([i64::MIN, -1 << 40, -3, -1, 0, 1, 5, i64::MAX]);
+        assert_monotone::<u128, TopBits<u128, 8>>([0, 1, 1 << 100, u128::MAX]);
+        assert_monotone::<usize, Log2Bucket>([0, 1, 2, 3, 4, usize::MAX]);
+    }
+
+    #[test]
+    fn bucket_counts() {
+        use super::{DivideBy, Log2Bucket, TopBits};
+        use crate::nested_queue::CoarseGrainedPriority;
+
+        assert_eq!(TopBits::<u8, 2>::coarse_grain(&0b1011_0000), TopBits(0b10));
+        assert_eq!(DivideBy::<i32, 10>::coarse_grain(&-1), DivideBy(-1));
+        assert_eq!(DivideBy::<i32, 10>::coarse_grain(&19), DivideBy(1));
+        assert_eq!(DivideBy::<u8, 255>::coarse_grain(&u8::MAX), DivideBy(1));
+        assert_eq!(DivideBy::<u8, 1000>::coarse_grain(&u8::MAX), DivideBy(0));
+        assert_eq!(DivideBy::<i8, 1000>::coarse_grain(&i8::MAX), DivideBy(0));
+        assert_eq!(DivideBy::<i8, 1000>::coarse_grain(&0), DivideBy(0));
+        assert_eq!(DivideBy::<i8, 1000>::coarse_grain(&i8::MIN), DivideBy(-1));
+        assert_eq!(
+            DivideBy::<i64, { u64::MAX }>::coarse_grain(&i64::MAX),
+            DivideBy(0)
+        );
+        assert_eq!(
+            DivideBy::<u64, { u64::MAX }>::coarse_grain(&u64::MAX),
+            DivideBy(1)
+        );
+        assert_eq!(Log2Bucket::coarse_grain(&0u32), Log2Bucket(0));
+        assert_eq!(Log2Bucket::coarse_grain(&7u32), Log2Bucket(3));
+        assert_eq!(Log2Bucket::coarse_grain(&8u32), Log2Bucket(4));
+        assert_eq!(Log2Bucket::coarse_grain(&-8i32), Log2Bucket(-4));
+    }
+
+    #[test]
+    fn time_monotone() {
+        use super::{DeadlineBucket, DurationBucket, InstantBucket, InstantGrainer};
+        use std::cmp::Reverse;
+        use std::time::{Duration, Instant};
+
+        let durations: Vec<_> = (0..500).map(|z| Duration::from_micros(z * 37)).collect();
+        assert_monotone::<Duration, DurationBucket<1_000>>(durations.iter().copied());
+        assert_monotone::<Duration, DurationBucket<1_000_000>>(durations.iter().copied());
+
+        let now = Instant::now();
+        let mut instants: Vec<_> = (0..200)
+            .map(|z| now + Duration::from_micros(z * 53))
+            .chain((1..200).filter_map(|z| now.checked_sub(Duration::from_micros(z * 53))))
+            .collect();
+        instants.sort();
+        let grainer = InstantGrainer::new(now);
+        assert_grainer_monotone::<Instant, InstantBucket<1_000>>(
+            &grainer,
+            instants.iter().copied(),
+        );
+        assert_grainer_monotone::<Instant, InstantBucket<100_000>>(
+            &grainer,
+            instants.iter().copied(),
+        );
+
+        let mut deadlines: Vec<_> = instants.into_iter().map(Reverse).collect();
+        deadlines.sort();
+        assert_grainer_monotone::<Reverse<Instant>, DeadlineBucket<1_000>>(&grainer, deadlines);
+        let mut deadlines: Vec<_> = durations.into_iter().map(Reverse).collect();
+        deadlines.sort();
+        assert_monotone::<Reverse<Duration>, DeadlineBucket<1_000>>(deadlines);
+    }
+
+    #[test]
+    fn epoch_per_grainer() {
+        use super::{DeadlineBucket, InstantBucket, InstantGrainer};
+        use crate::nested_queue::CoarseGrainer;
+        use std::cmp::Reverse;
+        use std::time::{Duration, Instant};
+
+        let now = Instant::now();
+        let later = InstantGrainer::new(now + Duration::from_micros(10));
+        let earlier = InstantGrainer::new(now);
+        assert_eq!(later.epoch(), now + Duration::from_micros(10));
+        // whichever grainer is used first, each counts from its own epoch
+        for _ in 0..2 {
+            let when = now + Duration::from_micros(25);
+            assert_eq!(later.coarse_grain(&when), InstantBucket::<1_000>(15));
+            assert_eq!(earlier.coarse_grain(&when), InstantBucket::<1_000>(25));
+            assert_eq!(
+                later.coarse_grain(&Reverse(when)),
+                DeadlineBucket::<1_000>(-15)
+            );
+            assert_eq!(
+                later.coarse_grain(&Reverse(now)),
+                DeadlineBucket::<1_000>(10)
+            );
+        }
+    }
+
+    #[test]
+    fn builtin_buckets_in_queue() {
+        use super::TopBits;
+        use crate::my_priority_queue::AbstractPriorityQueue;
+        use crate::nested_queue::BucketQueue;
+        use priority_queue::PriorityQueue;
+        use std::collections::HashMap;
+
+        let mut q = BucketQueue::<
+            u16,
+            u16,
+            TopBits<u16, 4>,
+            PriorityQueue<u16, u16>,
+            HashMap<TopBits<u16, 4>, PriorityQueue<u16, u16>>,
+        >::new(TopBits(0), TopBits(0), &PriorityQueue::new());
+        for z in [3, 60_000, 70, 4096, 0, 65_535, 8191] {
+            q.my_enqueue(z, z);
+        }
+        assert_eq!(q.my_len(), 7);
+        assert_eq!(q.my_peek(), Some((&65_535, &65_535)));
+        let all_out: Vec<_> = q.drain_all().into_iter().map(|(z, _)| z).collect();
+        assert_eq!(all_out, vec![65_535, 60_000, 8191, 4096, 70, 3, 0]);
+        assert!(q.is_empty());
+        assert!(q.my_dequeue().is_none());
+        assert!(q.my_peek().is_none());
+    }
+}
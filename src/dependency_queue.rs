@@ -1,7 +1,11 @@
-// TODO test
-
-use petgraph::{graph::NodeIndex, Graph};
-use std::cmp::Ordering;
+use petgraph::stable_graph::{NodeIndex, StableGraph};
+use priority_queue::PriorityQueue;
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    hash::Hash,
+};
 
 use crate::my_priority_queue::AbstractPriorityQueue;
 
@@ -11,158 +15,900 @@ pub trait Blocker {
     fn am_i_blocker(&self, other: &Self) -> bool;
 }
 
+/// an item declares which resources it reads and writes
+/// and a later item has to wait for an earlier one when they touch the same key
+/// and at least one of them writes it
+pub trait ResourceKeys {
+    type Key: Hash + Eq + Clone;
+    fn reads(&self) -> impl Iterator<Item = Self::Key> + '_;
+    fn writes(&self) -> impl Iterator<Item = Self::Key> + '_;
+}
+
+/// the relation `ResourceKeys` describes one pair at a time
+/// comparing every key of one with every key of the other instead of collecting them
+/// which is cheaper for the handful of keys an item usually touches
+fn keys_conflict<T: ResourceKeys>(earlier: &T, later: &T) -> bool {
+    earlier.writes().any(|key| {
+        later
+            .reads()
+            .chain(later.writes())
+            .any(|other| other == key)
+    }) || earlier
+        .reads()
+        .any(|key| later.writes().any(|other| other == key))
+}
+
+/// to use `ResourceKeys` items with `Pairwise`, which asks `Blocker` instead
+/// for items that do not have a `Blocker` of their own
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ByResourceKeys<T>(pub T);
+
+impl<T: ResourceKeys> Blocker for ByResourceKeys<T> {
+    fn am_i_blocker(&self, other: &Self) -> bool {
+        keys_conflict(&self.0, &other.0)
+    }
+}
+
+/// how a `BlockingQueue` finds which of the items already in it block a new one
+pub trait DependencyIndex<T> {
+    fn new() -> Self;
+    /// the nodes already in `dag` that `new_obj` has to wait for
+    /// `new_obj` was just added as `new_idx`
+    fn blockers<P>(
+        &mut self,
+        new_obj: &T,
+        new_idx: NodeIndex,
+        dag: &StableGraph<(T, P), ()>,
+    ) -> Vec<NodeIndex>;
+    /// `old_obj` which was `old_idx` was dequeued
+    fn forget(&mut self, old_obj: &T, old_idx: NodeIndex);
+    /// if `earlier` was enqueued before `later` do they have to be dequeued in that order too
+    /// only asked one pair at a time by `Validation`
+    fn is_blocker(&self, earlier: &T, later: &T) -> bool;
+}
+
+/// ask `Blocker::am_i_blocker` of every item already in the queue
+/// which costs time proportional to how many there are
+/// every item is asked, not only the ones nothing waits on yet
+/// because `am_i_blocker` need not be transitive
+/// so an item can block the new one even when nothing it blocks does
+pub struct Pairwise;
+
+impl<T: Blocker> DependencyIndex<T> for Pairwise {
+    fn new() -> Self {
+        Self
+    }
+
+    fn blockers<P>(
+        &mut self,
+        new_obj: &T,
+        new_idx: NodeIndex,
+        dag: &StableGraph<(T, P), ()>,
+    ) -> Vec<NodeIndex> {
+        dag.node_indices()
+            .filter(|temp_node_idx| {
+                *temp_node_idx != new_idx && dag[*temp_node_idx].0.am_i_blocker(new_obj)
+            })
+            .collect()
+    }
+
+    fn forget(&mut self, _old_obj: &T, _old_idx: NodeIndex) {}
+
+    fn is_blocker(&self, earlier: &T, later: &T) -> bool {
+        earlier.am_i_blocker(later)
+    }
+}
+
+/// a writer of a key and who has read it since, until the next writer
+/// the writer is None when it already left the queue, or nobody wrote the key yet
+struct KeyEpoch {
+    writer: Option<NodeIndex>,
+    readers: HashSet<NodeIndex>,
+}
+
+impl KeyEpoch {
+    fn is_empty(&self) -> bool {
+        self.writer.is_none() && self.readers.is_empty()
+    }
+}
+
+/// keeps every writer of each key still in the queue and who has read it since each of them
+/// a reader waits for the last writer
+/// and a writer waits for the last writer and all the readers since
+/// so enqueueing costs time proportional to the keys touched
+/// and everything else follows transitively giving the same order as `Pairwise`
+/// the earlier writers are kept so that when `FailurePolicy::CancelDependents`
+/// takes out the last writer, the one before it and its readers are what the next item waits on
+pub struct KeyedDependencies<K> {
+    /// oldest first, for every key that something in the queue touches
+    history: HashMap<K, VecDeque<KeyEpoch>>,
+}
+
+impl<K: Hash + Eq> KeyedDependencies<K> {
+    /// drop the epochs nobody is left in at either end of the history of `key`
+    /// those in the middle only empty out along with everything after them, when they are cancelled
+    fn prune(&mut self, key: &K) {
+        if let Some(epochs) = self.history.get_mut(key) {
+            while epochs.front().is_some_and(KeyEpoch::is_empty) {
+                epochs.pop_front();
+            }
+            while epochs.back().is_some_and(KeyEpoch::is_empty) {
+                epochs.pop_back();
+            }
+            if epochs.is_empty() {
+                self.history.remove(key);
+            }
+        }
+    }
+}
+
+/// the epoch in the history of a key that `has_it`
+/// one that was dequeued or completed is in the oldest, so that is tried first
+/// one that was cancelled could be in any of them, but most likely one of the newest
+fn find_epoch(
+    epochs: &mut VecDeque<KeyEpoch>,
+    has_it: impl Fn(&KeyEpoch) -> bool,
+) -> Option<&mut KeyEpoch> {
+    if epochs.front().is_some_and(&has_it) {
+        return epochs.front_mut();
+    }
+    epochs.iter_mut().rev().find(|epoch| has_it(epoch))
+}
+
+impl<T: ResourceKeys> DependencyIndex<T> for KeyedDependencies<T::Key> {
+    fn new() -> Self {
+        Self {
+            history: HashMap::new(),
+        }
+    }
+
+    fn blockers<P>(
+        &mut self,
+        new_obj: &T,
+        new_idx: NodeIndex,
+        _dag: &StableGraph<(T, P), ()>,
+    ) -> Vec<NodeIndex> {
+        let mut to_return = HashSet::new();
+        let new_writes: HashSet<T::Key> = new_obj.writes().collect();
+        for key in new_obj.reads().filter(|key| !new_writes.contains(key)) {
+            let epochs = self.history.entry(key).or_default();
+            if epochs.is_empty() {
+                epochs.push_back(KeyEpoch {
+                    writer: None,
+                    readers: HashSet::new(),
+                });
+            }
+            let latest = epochs.back_mut().expect("just made sure there is one");
+            to_return.extend(latest.writer);
+            latest.readers.insert(new_idx);
+        }
+        for key in new_writes {
+            let epochs = self.history.entry(key).or_default();
+            if let Some(latest) = epochs.back() {
+                to_return.extend(latest.writer);
+                to_return.extend(latest.readers.iter().copied());
+            }
+            epochs.push_back(KeyEpoch {
+                writer: Some(new_idx),
+                readers: HashSet::new(),
+            });
+        }
+        to_return.remove(&new_idx);
+        to_return.into_iter().collect()
+    }
+
+    fn forget(&mut self, old_obj: &T, old_idx: NodeIndex) {
+        let old_writes: HashSet<T::Key> = old_obj.writes().collect();
+        for key in old_obj.reads().filter(|key| !old_writes.contains(key)) {
+            if let Some(epoch) = self
+                .history
+                .get_mut(&key)
+                .and_then(|epochs| find_epoch(epochs, |epoch| epoch.readers.contains(&old_idx)))
+            {
+                epoch.readers.remove(&old_idx);
+            }
+            self.prune(&key);
+        }
+        for key in old_writes {
+            if let Some(epoch) = self
+                .history
+                .get_mut(&key)
+                .and_then(|epochs| find_epoch(epochs, |epoch| epoch.writer == Some(old_idx)))
+            {
+                epoch.writer = None;
+            }
+            self.prune(&key);
+        }
+    }
+
+    fn is_blocker(&self, earlier: &T, later: &T) -> bool {
+        keys_conflict(earlier, later)
+    }
+}
+
+/// what happens to a leased item that failed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// it is ready to be dequeued again
+    #[default]
+    Requeue,
+    /// it and everything it was blocking, transitively, are taken out of the queue
+    CancelDependents,
+}
+
+/// an item handed out by `BlockingQueue::lease` that has not finished yet
+/// give it back to `complete` or `fail`
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct Lease {
+    node_idx: NodeIndex,
+    lease_number: usize,
+}
+
+/// how much `BlockingQueue::try_enqueue` checks what `DependencyIndex::is_blocker` says
+/// against the items already in the queue, asking each of them both ways
+/// which is only meaningful when blocking is not supposed to be symmetric, unlike `ResourceKeys`
+/// these only ever reject items, they never change what waits on what
+/// with `KeyedDependencies` anything but `Off` asks every item in the queue again
+/// so enqueueing costs time proportional to the length of the queue like `Pairwise`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Validation {
+    /// trust it and only ask whether the items already there block the new one
+    #[default]
+    Off,
+    /// reject a new item that blocks and is blocked by the same item
+    Symmetric,
+    /// also reject a new item that claims to block something waiting
+    /// which already has to wait for something blocking the new item
+    Cycles,
+}
+
+/// why an item was rejected
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InconsistencyKind {
+    /// it and this node both claim to block the other
+    Symmetric(NodeIndex),
+    /// it would block the first of these, which block each other in turn
+    /// and the last of them blocks it
+    Cycle(Vec<NodeIndex>),
+}
+
+/// an item was enqueued that contradicts what the queue already has
+/// it is given back here
+pub struct Inconsistent<T, P> {
+    pub item: T,
+    pub priority: P,
+    pub kind: InconsistencyKind,
+}
+
+impl<T, P: fmt::Debug> fmt::Debug for Inconsistent<T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Inconsistent")
+            .field("priority", &self.priority)
+            .field("kind", &self.kind)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T, P: fmt::Debug> fmt::Display for Inconsistent<T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            InconsistencyKind::Symmetric(other) => write!(
+                f,
+                "item with priority {:?} and node {} both claim to block the other",
+                self.priority,
+                other.index()
+            ),
+            InconsistencyKind::Cycle(cycle) => write!(
+                f,
+                "item with priority {:?} would wait on itself through nodes {:?}",
+                self.priority,
+                cycle.iter().map(|z| z.index()).collect::<Vec<_>>()
+            ),
+        }
+    }
+}
+
+impl<T, P: fmt::Debug> std::error::Error for Inconsistent<T, P> {}
+
+/// a node as exported by `BlockingQueue::to_dot` and `BlockingQueue::to_json`
+struct ExportedNode<'a, P> {
+    id: usize,
+    label: String,
+    priority: &'a P,
+    is_source: bool,
+    is_sink: bool,
+    is_in_flight: bool,
+}
+
+fn dot_escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn json_escape(label: &str) -> String {
+    let mut to_return = String::with_capacity(label.len());
+    for c in label.chars() {
+        match c {
+            '"' => to_return.push_str("\\\""),
+            '\\' => to_return.push_str("\\\\"),
+            '\n' => to_return.push_str("\\n"),
+            c if u32::from(c) < 0x20 => to_return.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => to_return.push(c),
+        }
+    }
+    to_return
+}
+
+/// how a node's own priority combines with the effective priority of something it blocks
+type PriorityInheritance<P> = fn(&P, &P) -> P;
+
 /// imagine there are commands that are
 /// enqueued in arbitrary order and we are using priorities
 /// to shuffle around which order they get dequeue'd
 /// but there are some that still have to occur before others
 /// regardless of priorities
-pub struct BlockingQueue<T, P>
+/// which ones is found by `Deps`, asking every pair by default
+pub struct BlockingQueue<T, P, Deps = Pairwise>
 where
-    T: Blocker,
     P: Ord,
+    Deps: DependencyIndex<T>,
 {
-    my_dag: Graph<(T, P), ()>,
-    srcs: Vec<NodeIndex>,
-    sinks: Vec<NodeIndex>,
+    my_dag: StableGraph<(T, P), ()>,
+    /// the nodes nothing blocks anymore
+    /// ties go to whichever became ready first
+    srcs: PriorityQueue<NodeIndex, (P, Reverse<usize>)>,
+    ready_count: usize,
+    /// the nodes leased out and which lease each is
+    in_flight: HashMap<NodeIndex, usize>,
+    lease_count: usize,
+    failure_policy: FailurePolicy,
+    validation: Validation,
+    bidirectional_blocking: bool,
+    priority_inheritance: Option<PriorityInheritance<P>>,
+    /// the priorities the nodes inherit, when there is `priority_inheritance`
+    effective: HashMap<NodeIndex, P>,
+    dependencies: Deps,
 }
 
-impl<T, P> Default for BlockingQueue<T, P>
+impl<T, P, Deps> Default for BlockingQueue<T, P, Deps>
 where
     P: Ord,
-    T: Blocker,
+    Deps: DependencyIndex<T>,
 {
     fn default() -> Self {
         Self {
-            my_dag: Graph::default(),
-            srcs: Vec::default(),
-            sinks: Vec::default(),
+            my_dag: StableGraph::default(),
+            srcs: PriorityQueue::new(),
+            ready_count: 0,
+            in_flight: HashMap::new(),
+            lease_count: 0,
+            failure_policy: FailurePolicy::default(),
+            validation: Validation::default(),
+            bidirectional_blocking: false,
+            priority_inheritance: None,
+            effective: HashMap::new(),
+            dependencies: Deps::new(),
         }
     }
 }
 
-impl<T, P> AbstractPriorityQueue<T, P> for BlockingQueue<T, P>
+impl<T, P, Deps> BlockingQueue<T, P, Deps>
 where
-    T: Blocker + Clone,
     P: Ord + Clone,
+    Deps: DependencyIndex<T>,
 {
-    fn empty_copy(&self) -> Self {
-        let num_nodes = self.my_len();
-        let num_edges = self.my_dag.edge_count();
-        let num_srcs = self.srcs.len();
-        let num_snks = self.sinks.len();
-        Self {
-            my_dag: Graph::<_, _>::with_capacity(num_nodes, num_edges),
-            srcs: Vec::with_capacity(num_srcs),
-            sinks: Vec::with_capacity(num_snks),
+    /// nothing blocks `node_idx` anymore
+    fn make_ready(&mut self, node_idx: NodeIndex) {
+        let priority = self.effective_priority(node_idx).clone();
+        self.srcs
+            .push(node_idx, (priority, Reverse(self.ready_count)));
+        self.ready_count += 1;
+    }
+
+    /// what `node_idx` is ordered by among the ready nodes
+    fn effective_priority(&self, node_idx: NodeIndex) -> &P {
+        self.effective
+            .get(&node_idx)
+            .unwrap_or(&self.my_dag[node_idx].1)
+    }
+
+    /// the own priority of `node_idx` combined with the effective priorities of what it blocks
+    fn inherited(&self, node_idx: NodeIndex, priority_inheritance: PriorityInheritance<P>) -> P {
+        self.my_dag
+            .neighbors_directed(node_idx, petgraph::Direction::Outgoing)
+            .fold(self.my_dag[node_idx].1.clone(), |acc, dependent| {
+                priority_inheritance(&acc, self.effective_priority(dependent))
+            })
+    }
+
+    /// the effective priorities of `to_visit` might have changed
+    /// so recompute them and pass any changes on to what blocks them
+    fn propagate_inheritance(&mut self, mut to_visit: Vec<NodeIndex>) {
+        let Some(priority_inheritance) = self.priority_inheritance else {
+            return;
+        };
+        while let Some(cur_idx) = to_visit.pop() {
+            let new_effective = self.inherited(cur_idx, priority_inheritance);
+            if self.effective.get(&cur_idx) == Some(&new_effective) {
+                continue;
+            }
+            self.srcs
+                .change_priority_by(&cur_idx, |(w, _)| *w = new_effective.clone());
+            self.effective.insert(cur_idx, new_effective);
+            to_visit.extend(
+                self.my_dag
+                    .neighbors_directed(cur_idx, petgraph::Direction::Incoming),
+            );
         }
     }
 
-    fn my_peek(&self) -> Option<(&T, &P)> {
-        let mut srcs = self.srcs.iter().map(|id| {
-            self.my_dag
-                .node_weight(*id)
-                .expect("srcs are all valid nodes")
-        });
-        if let Some((first_src_item, first_src_priority)) = srcs.next() {
-            let to_return = srcs.fold((first_src_item, first_src_priority), |acc, next| {
-                if acc.1.cmp(&next.1) == Ordering::Less {
-                    (&next.0, &next.1)
-                } else {
-                    acc
-                }
-            });
-            Some(to_return)
-        } else {
-            None
+    /// order the ready nodes by the combination of their own priority
+    /// and those of everything they transitively block
+    /// so something urgent is not stuck behind something that is not
+    /// `priority_inheritance` combines a priority with that of one dependent at a time
+    /// like taking the bigger of the two
+    /// the priorities given back are still the ones the items were enqueued with
+    /// # Panics
+    /// if the blocking relations are not acyclic
+    /// which `try_enqueue` never allows, even with bidirectional blocking
+    pub fn change_priority_inheritance(
+        &mut self,
+        priority_inheritance: Option<PriorityInheritance<P>>,
+    ) {
+        self.priority_inheritance = priority_inheritance;
+        self.effective.clear();
+        if let Some(priority_inheritance) = priority_inheritance {
+            let in_order = petgraph::algo::toposort(&self.my_dag, None)
+                .expect("the blocking relations are acyclic");
+            for cur_idx in in_order.into_iter().rev() {
+                let new_effective = self.inherited(cur_idx, priority_inheritance);
+                self.effective.insert(cur_idx, new_effective);
+            }
+        }
+        let ready: Vec<_> = self.srcs.iter().map(|(z, _)| *z).collect();
+        for src_idx in ready {
+            let new_effective = self.effective_priority(src_idx).clone();
+            self.srcs
+                .change_priority_by(&src_idx, |(w, _)| *w = new_effective);
         }
     }
 
-    fn my_enqueue(&mut self, new_obj: T, new_obj_priority: P) {
-        let cur_node_idx = self
+    /// `node_idx` is done so take it out and ready whatever only it was blocking
+    fn release(&mut self, node_idx: NodeIndex) -> Option<(T, P)> {
+        let neighbor_idces: Vec<_> = self
             .my_dag
-            .add_node((new_obj.clone(), new_obj_priority.clone()));
-        let mut new_sinks = std::mem::take(&mut self.sinks);
-        let mut removals = Vec::new();
-        for (cur_sink_idx, cur_sink) in new_sinks.iter().enumerate() {
-            let cur_item = self
+            .neighbors_directed(node_idx, petgraph::Direction::Outgoing)
+            .collect();
+        for neighbor in neighbor_idces {
+            let count_incoming = self
                 .my_dag
-                .node_weight(*cur_sink)
-                .expect("sinks exist in graph");
-            if cur_item.0.am_i_blocker(&new_obj) {
-                removals.push(cur_sink_idx);
-                self.my_dag.add_edge(*cur_sink, cur_node_idx, ());
-            }
-        }
-        if removals.is_empty() {
-            let mut is_cur_also_src = true;
-            for temp_node_idx in self.my_dag.node_indices() {
-                let temp_item = &self
-                    .my_dag
-                    .node_weight(temp_node_idx)
-                    .expect("Already checked exists")
-                    .0;
-                if temp_item.am_i_blocker(&new_obj) {
-                    is_cur_also_src = false;
-                    self.my_dag.add_edge(temp_node_idx, cur_node_idx, ());
-                }
-            }
-            if is_cur_also_src {
-                self.srcs.push(cur_node_idx);
+                .neighbors_directed(neighbor, petgraph::Direction::Incoming)
+                .count();
+            if count_incoming <= 1 {
+                self.make_ready(neighbor);
             }
+        }
+        let (old_obj, _) = &self.my_dag[node_idx];
+        self.dependencies.forget(old_obj, node_idx);
+        // nothing waiting blocks it so no one else inherited from it
+        self.effective.remove(&node_idx);
+        self.my_dag.remove_node(node_idx)
+    }
+
+    /// what `fail` does with the item
+    pub fn change_failure_policy(&mut self, failure_policy: FailurePolicy) {
+        self.failure_policy = failure_policy;
+    }
+
+    /// like `my_dequeue` but the item stays in the queue blocking the same things
+    /// until the `Lease` comes back to `complete` or `fail`
+    pub fn lease(&mut self) -> Option<(Lease, T, P)>
+    where
+        T: Clone,
+    {
+        let (src_idx, _) = self.srcs.pop()?;
+        let lease_number = self.lease_count;
+        self.lease_count += 1;
+        self.in_flight.insert(src_idx, lease_number);
+        let (src, src_priority) = &self.my_dag[src_idx];
+        Some((
+            Lease {
+                node_idx: src_idx,
+                lease_number,
+            },
+            src.clone(),
+            src_priority.clone(),
+        ))
+    }
+
+    /// is this one of the leases handed out and not yet given back
+    fn take_lease(&mut self, lease: &Lease) -> bool {
+        if self.in_flight.get(&lease.node_idx) == Some(&lease.lease_number) {
+            self.in_flight.remove(&lease.node_idx);
+            true
         } else {
-            removals.sort();
-            for removal in removals.iter().rev() {
-                new_sinks.remove(*removal);
+            false
+        }
+    }
+
+    /// the leased item finished so what it was blocking can go ahead
+    /// gives it back, or None if the lease was not from this queue
+    pub fn complete(&mut self, lease: Lease) -> Option<(T, P)> {
+        if !self.take_lease(&lease) {
+            return None;
+        }
+        self.release(lease.node_idx)
+    }
+
+    /// the leased item did not finish, see `FailurePolicy`
+    /// gives back everything that was cancelled, starting with it
+    /// or None if the lease was not from this queue
+    pub fn fail(&mut self, lease: Lease) -> Option<Vec<(T, P)>> {
+        if !self.take_lease(&lease) {
+            return None;
+        }
+        match self.failure_policy {
+            FailurePolicy::Requeue => {
+                self.make_ready(lease.node_idx);
+                Some(vec![])
+            }
+            FailurePolicy::CancelDependents => {
+                let mut cancelled = vec![lease.node_idx];
+                let mut seen = HashSet::from([lease.node_idx]);
+                let mut which = 0;
+                while which < cancelled.len() {
+                    for dependent in self
+                        .my_dag
+                        .neighbors_directed(cancelled[which], petgraph::Direction::Outgoing)
+                    {
+                        if seen.insert(dependent) {
+                            cancelled.push(dependent);
+                        }
+                    }
+                    which += 1;
+                }
+                // the others blocking the cancelled ones no longer inherit from them
+                let still_blocking: Vec<_> = cancelled
+                    .iter()
+                    .flat_map(|cur_idx| {
+                        self.my_dag
+                            .neighbors_directed(*cur_idx, petgraph::Direction::Incoming)
+                    })
+                    .filter(|blocker| !seen.contains(blocker))
+                    .collect();
+                // dependents are never ready while something before them is waiting
+                let to_return = cancelled
+                    .into_iter()
+                    .filter_map(|cur_idx| {
+                        let (old_obj, _) = &self.my_dag[cur_idx];
+                        self.dependencies.forget(old_obj, cur_idx);
+                        self.effective.remove(&cur_idx);
+                        self.my_dag.remove_node(cur_idx)
+                    })
+                    .collect();
+                self.propagate_inheritance(still_blocking);
+                Some(to_return)
             }
         }
-        new_sinks.push(cur_node_idx);
-        self.sinks = new_sinks;
     }
 
-    fn enqueue_batch(&mut self, new_batch: impl IntoIterator<Item = T>, new_batch_priority: P) {
-        for item in new_batch {
-            self.my_enqueue(item, new_batch_priority.clone());
+    /// what `try_enqueue` checks
+    pub fn change_validation(&mut self, validation: Validation) {
+        self.validation = validation;
+    }
+
+    /// when on, a new item also blocks the items already waiting that it claims to block
+    /// so they wait for it too, except those in flight which already started
+    /// whatever the `Validation`, a new item that would make something wait on itself is rejected
+    /// this asks every item in the queue both ways, so enqueueing costs time proportional to its length
+    pub fn change_bidirectional_blocking(&mut self, bidirectional_blocking: bool) {
+        self.bidirectional_blocking = bidirectional_blocking;
+    }
+
+    /// the item at `node_idx` like those in an `InconsistencyKind`
+    pub fn item_at(&self, node_idx: NodeIndex) -> Option<(&T, &P)> {
+        self.my_dag.node_weight(node_idx).map(|(z, w)| (z, w))
+    }
+
+    /// the first of `starts` that reaches one of `ends` in the graph
+    /// and the path from it
+    fn find_path(&self, starts: &[NodeIndex], ends: &HashSet<NodeIndex>) -> Option<Vec<NodeIndex>> {
+        let mut came_from = HashMap::new();
+        let mut to_visit = VecDeque::new();
+        for start in starts {
+            if came_from.insert(*start, None).is_none() {
+                to_visit.push_back(*start);
+            }
+        }
+        while let Some(cur_idx) = to_visit.pop_front() {
+            if ends.contains(&cur_idx) {
+                let mut path = vec![cur_idx];
+                while let Some(Some(before)) = came_from.get(path.last().expect("nonempty")) {
+                    path.push(*before);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for next_idx in self
+                .my_dag
+                .neighbors_directed(cur_idx, petgraph::Direction::Outgoing)
+            {
+                if let std::collections::hash_map::Entry::Vacant(e) = came_from.entry(next_idx) {
+                    e.insert(Some(cur_idx));
+                    to_visit.push_back(next_idx);
+                }
+            }
         }
+        None
     }
 
-    fn my_dequeue(&mut self) -> Option<(T, P)> {
-        let new_srcs_1 = std::mem::take(&mut self.srcs);
-        let mut new_srcs_2 = new_srcs_1.clone();
-        let srcs = new_srcs_1
-            .iter()
-            .map(|s| (s, self.my_dag.node_weight(*s).expect("srcs exist")));
-        let mut highest_priority_info = None;
-        for (which_src, (cur_src_idx, (cur_src, cur_src_priority))) in srcs.enumerate() {
-            if let Some((_, _, _, highest_priority)) = &highest_priority_info {
-                if cur_src_priority.cmp(highest_priority) == Ordering::Greater {
-                    highest_priority_info =
-                        Some((which_src, cur_src_idx, cur_src, cur_src_priority.clone()));
+    /// like `my_enqueue` but checks the new item according to the `Validation`
+    /// and gives back its node, for `item_at` and `why_blocked`
+    /// this costs time proportional to the length of the queue
+    /// unless validation is `Off` and blocking is not bidirectional
+    /// # Errors
+    /// if the new item contradicts the ones already in the queue
+    pub fn try_enqueue(
+        &mut self,
+        new_obj: T,
+        new_obj_priority: P,
+    ) -> Result<NodeIndex, Inconsistent<T, P>> {
+        let mut claimed = vec![];
+        if self.validation != Validation::Off || self.bidirectional_blocking {
+            let mut blocks_new = HashSet::new();
+            for (cur_idx, (cur_obj, _)) in self.my_dag.node_indices().map(|z| (z, &self.my_dag[z]))
+            {
+                let new_blocks_cur = self.dependencies.is_blocker(&new_obj, cur_obj);
+                if self.dependencies.is_blocker(cur_obj, &new_obj) {
+                    if new_blocks_cur {
+                        return Err(Inconsistent {
+                            item: new_obj,
+                            priority: new_obj_priority,
+                            kind: InconsistencyKind::Symmetric(cur_idx),
+                        });
+                    }
+                    blocks_new.insert(cur_idx);
+                } else if new_blocks_cur && !self.in_flight.contains_key(&cur_idx) {
+                    // those in flight already started so nothing can come before them
+                    claimed.push(cur_idx);
                 }
-            } else {
-                highest_priority_info =
-                    Some((which_src, cur_src_idx, cur_src, cur_src_priority.clone()));
+            }
+            if self.validation == Validation::Cycles || self.bidirectional_blocking {
+                if let Some(cycle) = self.find_path(&claimed, &blocks_new) {
+                    return Err(Inconsistent {
+                        item: new_obj,
+                        priority: new_obj_priority,
+                        kind: InconsistencyKind::Cycle(cycle),
+                    });
+                }
+            }
+            if !self.bidirectional_blocking {
+                claimed.clear();
             }
         }
-        if let Some((z0, z1, _z2, _z3)) = highest_priority_info {
-            new_srcs_2.remove(z0);
-            let neighbor_idces = self
+        let cur_node_idx = self.my_dag.add_node((new_obj, new_obj_priority));
+        let blockers =
+            self.dependencies
+                .blockers(&self.my_dag[cur_node_idx].0, cur_node_idx, &self.my_dag);
+        let is_ready = blockers.is_empty();
+        for blocker in blockers {
+            self.my_dag.add_edge(blocker, cur_node_idx, ());
+        }
+        for claimed_idx in claimed {
+            self.srcs.remove(&claimed_idx);
+            self.my_dag.add_edge(cur_node_idx, claimed_idx, ());
+        }
+        self.propagate_inheritance(vec![cur_node_idx]);
+        if is_ready {
+            self.make_ready(cur_node_idx);
+        }
+        Ok(cur_node_idx)
+    }
+
+    /// what is ahead of the item at `item_idx`, starting from something ready or in flight
+    /// and ending with one that it waits on directly
+    /// empty when it is not waiting on anything, None when there is no item at `item_idx`
+    /// the nodes come from `try_enqueue`, and are the ids in `to_dot` and `to_json`
+    /// like for `item_at`, once an item leaves the queue its node can be given to a new item
+    pub fn why_blocked(&self, item_idx: NodeIndex) -> Option<Vec<(&T, &P)>> {
+        if !self.my_dag.contains_node(item_idx) {
+            return None;
+        }
+        // the closest thing with no blockers of its own going backwards
+        let mut came_from = HashMap::from([(item_idx, None)]);
+        let mut to_visit = VecDeque::from([item_idx]);
+        while let Some(cur_idx) = to_visit.pop_front() {
+            let mut blockers = self
                 .my_dag
-                .neighbors_directed(*z1, petgraph::Direction::Outgoing);
-            for neighbor in neighbor_idces {
-                let count_incoming = self
-                    .my_dag
-                    .neighbors_directed(neighbor, petgraph::Direction::Incoming)
-                    .count();
-                if count_incoming <= 1 {
-                    new_srcs_2.push(neighbor);
+                .neighbors_directed(cur_idx, petgraph::Direction::Incoming)
+                .peekable();
+            if blockers.peek().is_none() {
+                let mut chain = vec![];
+                let mut chain_idx = cur_idx;
+                while chain_idx != item_idx {
+                    let (z, w) = &self.my_dag[chain_idx];
+                    chain.push((z, w));
+                    chain_idx = came_from[&chain_idx].expect("on the way to the item");
+                }
+                return Some(chain);
+            }
+            for blocker in blockers {
+                if let std::collections::hash_map::Entry::Vacant(e) = came_from.entry(blocker) {
+                    e.insert(Some(cur_idx));
+                    to_visit.push_back(blocker);
                 }
             }
-            let pulled_out = self.my_dag.remove_node(*z1);
-            self.srcs = new_srcs_2;
-            pulled_out
-        } else {
-            None
         }
+        unreachable!("the blocking relations are acyclic so going backwards reaches a source")
+    }
+
+    /// nodes with what `label` says about the items, their priority
+    /// whether they are sources, sinks or in flight
+    /// and the edges from blockers to what they block
+    fn export_nodes(
+        &self,
+        label: impl Fn(&T) -> String,
+    ) -> (Vec<ExportedNode<'_, P>>, Vec<(usize, usize)>) {
+        let nodes = self
+            .my_dag
+            .node_indices()
+            .map(|z| {
+                let (item, priority) = &self.my_dag[z];
+                ExportedNode {
+                    id: z.index(),
+                    label: label(item),
+                    priority,
+                    is_source: self
+                        .my_dag
+                        .neighbors_directed(z, petgraph::Direction::Incoming)
+                        .next()
+                        .is_none(),
+                    is_sink: self
+                        .my_dag
+                        .neighbors_directed(z, petgraph::Direction::Outgoing)
+                        .next()
+                        .is_none(),
+                    is_in_flight: self.in_flight.contains_key(&z),
+                }
+            })
+            .collect();
+        let edges = self
+            .my_dag
+            .edge_indices()
+            .filter_map(|e| self.my_dag.edge_endpoints(e))
+            .map(|(from, to)| (from.index(), to.index()))
+            .collect();
+        (nodes, edges)
+    }
+
+    /// the blocking relations in Graphviz DOT
+    /// each node is labelled by `label` of its item and its priority
+    /// the sources are filled in, those in flight in a different color
+    /// and the sinks have a double border
+    pub fn to_dot(&self, label: impl Fn(&T) -> String) -> String
+    where
+        P: fmt::Debug,
+    {
+        let (nodes, edges) = self.export_nodes(label);
+        let mut to_return = String::from("digraph {\n");
+        for node in nodes {
+            let mut attributes = vec![format!(
+                "label=\"{}\"",
+                dot_escape(&format!("{}\npriority {:?}", node.label, node.priority))
+            )];
+            if node.is_in_flight {
+                attributes.push("style=filled, fillcolor=lightblue".to_string());
+            } else if node.is_source {
+                attributes.push("style=filled, fillcolor=palegreen".to_string());
+            }
+            if node.is_sink {
+                attributes.push("peripheries=2".to_string());
+            }
+            to_return.push_str(&format!("    {} [{}]\n", node.id, attributes.join(", ")));
+        }
+        for (from, to) in edges {
+            to_return.push_str(&format!("    {from} -> {to}\n"));
+        }
+        to_return.push_str("}\n");
+        to_return
+    }
+
+    /// the blocking relations as JSON like
+    /// `{"nodes": [{"id": 0, "label": "..", "priority": "..", "source": true, "sink": true, "in_flight": false}], "edges": [{"from": 0, "to": 1}]}`
+    /// where the label is `label` of the item and the priority is its `Debug`
+    pub fn to_json(&self, label: impl Fn(&T) -> String) -> String
+    where
+        P: fmt::Debug,
+    {
+        let (nodes, edges) = self.export_nodes(label);
+        let nodes: Vec<_> = nodes
+            .into_iter()
+            .map(|node| {
+                format!(
+                    "{{\"id\": {}, \"label\": \"{}\", \"priority\": \"{}\", \"source\": {}, \"sink\": {}, \"in_flight\": {}}}",
+                    node.id,
+                    json_escape(&node.label),
+                    json_escape(&format!("{:?}", node.priority)),
+                    node.is_source,
+                    node.is_sink,
+                    node.is_in_flight
+                )
+            })
+            .collect();
+        let edges: Vec<_> = edges
+            .into_iter()
+            .map(|(from, to)| format!("{{\"from\": {from}, \"to\": {to}}}"))
+            .collect();
+        format!(
+            "{{\"nodes\": [{}], \"edges\": [{}]}}",
+            nodes.join(", "),
+            edges.join(", ")
+        )
+    }
+
+    /// how many items are leased out and not yet completed or failed
+    /// these are not counted in `my_len`
+    pub fn in_flight_len(&self) -> usize {
+        self.in_flight.len()
+    }
+}
+
+impl<T, P, Deps> AbstractPriorityQueue<T, P> for BlockingQueue<T, P, Deps>
+where
+    P: Ord + Clone,
+    Deps: DependencyIndex<T>,
+{
+    fn empty_copy(&self) -> Self {
+        let num_nodes = self.my_len();
+        let num_edges = self.my_dag.edge_count();
+        let num_srcs = self.srcs.len();
+        Self {
+            my_dag: StableGraph::<_, _>::with_capacity(num_nodes, num_edges),
+            srcs: PriorityQueue::with_capacity(num_srcs),
+            ready_count: 0,
+            in_flight: HashMap::new(),
+            lease_count: 0,
+            failure_policy: self.failure_policy,
+            validation: self.validation,
+            bidirectional_blocking: self.bidirectional_blocking,
+            priority_inheritance: self.priority_inheritance,
+            effective: HashMap::new(),
+            dependencies: Deps::new(),
+        }
+    }
+
+    fn my_peek(&self) -> Option<(&T, &P)> {
+        let (src_idx, _) = self.srcs.peek()?;
+        let (src, src_priority) = self
+            .my_dag
+            .node_weight(*src_idx)
+            .expect("srcs are all valid nodes");
+        Some((src, src_priority))
+    }
+
+    /// # Panics
+    /// if the new item contradicts the ones already in the queue
+    /// which can only happen when validation is not `Off`
+    /// use `try_enqueue` to get that back as an error instead
+    fn my_enqueue(&mut self, new_obj: T, new_obj_priority: P) {
+        let enqueued = self.try_enqueue(new_obj, new_obj_priority);
+        assert!(
+            enqueued.is_ok(),
+            "an item was enqueued that contradicts what blocks what in the BlockingQueue"
+        );
+    }
+
+    fn enqueue_batch(&mut self, new_batch: impl IntoIterator<Item = T>, new_batch_priority: P) {
+        for item in new_batch {
+            self.my_enqueue(item, new_batch_priority.clone());
+        }
+    }
+
+    fn my_dequeue(&mut self) -> Option<(T, P)> {
+        let (src_idx, _) = self.srcs.pop()?;
+        self.release(src_idx)
     }
 
     fn dequeue_batch(&mut self, around_how_many: usize, hard_limit: usize) -> Vec<(T, P)> {
@@ -180,7 +926,7 @@ where
     }
 
     fn my_len(&self) -> usize {
-        self.my_dag.node_count()
+        self.my_dag.node_count() - self.in_flight.len()
     }
 
     fn is_empty(&self) -> bool {
@@ -189,9 +935,638 @@ where
 }
 
 mod test {
+    use super::ResourceKeys;
+
+    /// a task touching some of the keys 0 through 5
+    #[allow(dead_code)]
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Task {
+        id: usize,
+        reads: Vec<u8>,
+        writes: Vec<u8>,
+    }
+
+    /// a `Task` from the keys it reads and writes
+    #[allow(dead_code)]
+    fn task(id: usize, reads: &[u8], writes: &[u8]) -> Task {
+        Task {
+            id,
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+        }
+    }
+
+    impl ResourceKeys for Task {
+        type Key = u8;
+
+        fn reads(&self) -> impl Iterator<Item = u8> + '_ {
+            self.reads.iter().copied()
+        }
+
+        fn writes(&self) -> impl Iterator<Item = u8> + '_ {
+            self.writes.iter().copied()
+        }
+    }
+
+    /// now that `ResourceKeys` does not decide it, a type can have its own `Blocker` as well
+    impl super::Blocker for Task {
+        fn am_i_blocker(&self, other: &Self) -> bool {
+            super::keys_conflict(self, other)
+        }
+    }
+
+    /// `out` was just dequeued, so nothing enqueued before it
+    /// and still waiting can block it
+    #[allow(dead_code)]
+    fn take_out<T: super::Blocker + PartialEq>(waiting: &mut Vec<T>, out: &T) {
+        let position = waiting
+            .iter()
+            .position(|z| z == out)
+            .expect("it was waiting");
+        assert!(waiting[..position].iter().all(|z| !z.am_i_blocker(out)));
+        waiting.remove(position);
+    }
+
+    /// blocks according to an arbitrary relation which is not transitive
+    #[allow(dead_code)]
+    #[derive(Debug, PartialEq, Eq)]
+    struct Scrambled(usize);
+
+    impl super::Blocker for Scrambled {
+        fn am_i_blocker(&self, other: &Self) -> bool {
+            (self.0 * 7 + other.0 * 13).is_multiple_of(5)
+        }
+    }
+
+    /// a linear congruential generator starting from `seed`
+    /// giving numbers below the modulus it is called with
+    #[allow(dead_code)]
+    fn seeded_random(seed: usize) -> impl FnMut(usize) -> usize {
+        let mut step = seed;
+        move |modulus| {
+            step = step
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (step >> 33) % modulus
+        }
+    }
+
+    /// enqueue and dequeue some random tasks with all different priorities
+    /// checking each dequeue was not blocked by something still waiting
+    #[allow(dead_code)]
+    fn random_tasks<Q: crate::AbstractPriorityQueue<Task, usize>>(
+        mut q: Q,
+        seed: usize,
+    ) -> Vec<usize> {
+        let mut next_random = seeded_random(seed);
+        let mut waiting: Vec<Task> = Vec::new();
+        let mut dequeued = Vec::new();
+        for id in 0..120 {
+            let mut reads = Vec::new();
+            let mut writes = Vec::new();
+            for key in 0..6 {
+                match next_random(8) {
+                    0 => writes.push(key),
+                    1 | 2 => reads.push(key),
+                    3 => {
+                        reads.push(key);
+                        writes.push(key);
+                    }
+                    _ => {}
+                }
+            }
+            let task = Task { id, reads, writes };
+            waiting.push(task.clone());
+            q.my_enqueue(task, (id * 7919) % 1009);
+            if next_random(3) == 0 {
+                for (out, _) in q.dequeue_batch(2, 2) {
+                    take_out(&mut waiting, &out);
+                    dequeued.push(out.id);
+                }
+            }
+        }
+        while let Some((out, _)) = q.my_dequeue() {
+            take_out(&mut waiting, &out);
+            dequeued.push(out.id);
+        }
+        assert!(waiting.is_empty());
+        dequeued
+    }
+
+    #[test]
+    fn nothing_in_out() {
+        use super::{BlockingQueue, KeyedDependencies};
+        use crate::AbstractPriorityQueue;
+
+        let mut q = BlockingQueue::<Task, usize, KeyedDependencies<u8>>::default();
+        assert_eq!(q.my_len(), 0);
+        assert!(q.is_empty());
+        assert!(q.my_peek().is_none());
+        assert!(q.my_dequeue().is_none());
+        assert!(q.dequeue_batch(10, 10).is_empty());
+    }
+
+    #[test]
+    fn readers_and_writers() {
+        use super::{BlockingQueue, KeyedDependencies};
+        use crate::AbstractPriorityQueue;
+
+        let mut q = BlockingQueue::<Task, usize, KeyedDependencies<u8>>::default();
+        q.my_enqueue(task(0, &[], &[1]), 0);
+        q.my_enqueue(task(1, &[1], &[]), 5);
+        q.my_enqueue(task(2, &[1], &[]), 6);
+        q.my_enqueue(task(3, &[], &[1]), 9);
+        q.my_enqueue(task(4, &[2], &[]), 1);
+        // only the first writer and the unrelated reader are ready
+        assert_eq!(q.my_peek().map(|(z, _)| z.id), Some(4));
+        let all_out: Vec<_> = q.drain_all().into_iter().map(|(z, _)| z.id).collect();
+        assert_eq!(all_out, vec![4, 0, 2, 1, 3]);
+    }
 
     #[test]
-    fn presence() {
-        // TODO stub
+    fn keyed_same_as_pairwise() {
+        use super::{BlockingQueue, KeyedDependencies};
+
+        for seed in 0..20 {
+            let pairwise = random_tasks(BlockingQueue::<Task, usize>::default(), seed);
+            let keyed = random_tasks(
+                BlockingQueue::<Task, usize, KeyedDependencies<u8>>::default(),
+                seed,
+            );
+            assert_eq!(pairwise.len(), 120);
+            assert_eq!(pairwise, keyed);
+        }
+    }
+
+    #[test]
+    fn by_resource_keys() {
+        use super::{BlockingQueue, ByResourceKeys, KeyedDependencies};
+        use crate::AbstractPriorityQueue;
+
+        let tasks = [
+            task(0, &[2], &[1]),
+            task(1, &[1], &[]),
+            task(2, &[], &[2]),
+            task(3, &[1, 2], &[3]),
+            task(4, &[3], &[]),
+        ];
+        let mut pairwise = BlockingQueue::<ByResourceKeys<Task>, usize>::default();
+        let mut keyed = BlockingQueue::<Task, usize, KeyedDependencies<u8>>::default();
+        for (z, priority) in tasks.into_iter().zip([1, 9, 8, 7, 6]) {
+            pairwise.my_enqueue(ByResourceKeys(z.clone()), priority);
+            keyed.my_enqueue(z, priority);
+        }
+        let pairwise_out: Vec<_> = pairwise
+            .drain_all()
+            .into_iter()
+            .map(|(z, _)| z.0.id)
+            .collect();
+        let keyed_out: Vec<_> = keyed.drain_all().into_iter().map(|(z, _)| z.id).collect();
+        assert_eq!(pairwise_out, vec![0, 1, 2, 3, 4]);
+        assert_eq!(pairwise_out, keyed_out);
+    }
+
+    #[test]
+    fn pairwise_asks_every_node() {
+        use super::BlockingQueue;
+        use crate::AbstractPriorityQueue;
+
+        /// blocks exactly the ones that list it
+        #[derive(Debug, PartialEq, Eq)]
+        struct Listed {
+            id: usize,
+            blocked_by: Vec<usize>,
+        }
+        impl super::Blocker for Listed {
+            fn am_i_blocker(&self, other: &Self) -> bool {
+                other.blocked_by.contains(&self.id)
+            }
+        }
+
+        let mut q = BlockingQueue::<Listed, usize>::default();
+        q.my_enqueue(
+            Listed {
+                id: 0,
+                blocked_by: vec![],
+            },
+            1,
+        );
+        q.my_enqueue(
+            Listed {
+                id: 1,
+                blocked_by: vec![0],
+            },
+            2,
+        );
+        // 0 already blocks 1 so only 1 has nothing waiting on it
+        // but 0 blocks this one as well while 1 does not
+        q.my_enqueue(
+            Listed {
+                id: 2,
+                blocked_by: vec![0],
+            },
+            10,
+        );
+        let all_out: Vec<_> = q.drain_all().into_iter().map(|(z, _)| z.id).collect();
+        assert_eq!(all_out, vec![0, 2, 1]);
+    }
+
+    #[test]
+    fn random_topological_orders() {
+        use super::BlockingQueue;
+        use crate::AbstractPriorityQueue;
+
+        for seed in 0..30 {
+            let mut next_random = seeded_random(seed);
+            let mut q = BlockingQueue::<Scrambled, usize>::default();
+            if seed % 2 == 1 {
+                q.change_priority_inheritance(Some(|p1, p2| *p1.max(p2)));
+            }
+            let mut waiting = Vec::new();
+            let mut how_many_out = 0;
+            for id in 0..150 {
+                q.my_enqueue(Scrambled(id), next_random(10));
+                waiting.push(Scrambled(id));
+                while next_random(2) == 0 {
+                    let peeked = q.my_peek().map(|(z, w)| (z.0, *w));
+                    let Some((out, out_priority)) = q.my_dequeue() else {
+                        break;
+                    };
+                    assert_eq!(peeked, Some((out.0, out_priority)));
+                    take_out(&mut waiting, &out);
+                    how_many_out += 1;
+                }
+                assert_eq!(q.my_len(), waiting.len());
+            }
+            for (out, _) in q.drain_all() {
+                take_out(&mut waiting, &out);
+                how_many_out += 1;
+            }
+            assert!(waiting.is_empty());
+            assert_eq!(how_many_out, 150);
+        }
+    }
+
+    #[test]
+    fn leases() {
+        use super::{BlockingQueue, KeyedDependencies};
+        use crate::AbstractPriorityQueue;
+
+        let mut q = BlockingQueue::<Task, usize, KeyedDependencies<u8>>::default();
+        q.my_enqueue(task(0, &[], &[1]), 0);
+        q.my_enqueue(task(1, &[1], &[]), 5);
+        q.my_enqueue(task(2, &[3], &[]), 1);
+        let (lease_2, leased, _) = q.lease().expect("two are ready");
+        assert_eq!(leased.id, 2);
+        let (lease_0, leased, _) = q.lease().expect("one more is ready");
+        assert_eq!(leased.id, 0);
+        assert_eq!((q.my_len(), q.in_flight_len()), (1, 2));
+        // still waiting on the writer even though it was handed out
+        assert!(q.my_peek().is_none());
+        assert!(q.my_dequeue().is_none());
+        q.my_enqueue(task(3, &[1], &[]), 9);
+        assert!(q.my_peek().is_none());
+        assert_eq!(q.complete(lease_0).map(|(z, _)| z.id), Some(0));
+        assert_eq!(q.my_peek().map(|(z, _)| z.id), Some(3));
+        assert_eq!((q.my_len(), q.in_flight_len()), (2, 1));
+        assert_eq!(q.fail(lease_2), Some(vec![]));
+        assert_eq!((q.my_len(), q.in_flight_len()), (3, 0));
+        let all_out: Vec<_> = q.drain_all().into_iter().map(|(z, _)| z.id).collect();
+        assert_eq!(all_out, vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn failure_cancels_dependents() {
+        use super::{BlockingQueue, FailurePolicy, KeyedDependencies, Lease};
+        use crate::AbstractPriorityQueue;
+        use petgraph::stable_graph::NodeIndex;
+
+        let mut q = BlockingQueue::<Task, usize, KeyedDependencies<u8>>::default();
+        q.change_failure_policy(FailurePolicy::CancelDependents);
+        q.my_enqueue(task(0, &[], &[1]), 0);
+        q.my_enqueue(task(1, &[1], &[2]), 5);
+        q.my_enqueue(task(2, &[2], &[]), 7);
+        q.my_enqueue(task(3, &[4], &[]), 1);
+        let (lease_3, _, _) = q.lease().expect("two are ready");
+        let (lease_0, _, _) = q.lease().expect("one more is ready");
+        let cancelled: Vec<_> = q
+            .fail(lease_0)
+            .expect("it was leased")
+            .into_iter()
+            .map(|(z, _)| z.id)
+            .collect();
+        assert_eq!(cancelled, vec![0, 1, 2]);
+        assert_eq!((q.my_len(), q.in_flight_len()), (0, 1));
+        let not_ours = Lease {
+            node_idx: NodeIndex::new(0),
+            lease_number: 99,
+        };
+        assert!(q.complete(not_ours).is_none());
+        assert_eq!(q.complete(lease_3).map(|(z, _)| z.id), Some(3));
+        assert!(q.is_empty());
+        assert_eq!(q.in_flight_len(), 0);
+    }
+
+    /// lease and complete or fail some random tasks, cancelling what the failed ones block
+    /// what came out in order, with the ones cancelled together sorted
+    /// and whether they were cancelled
+    #[allow(dead_code)]
+    fn random_failures<D: super::DependencyIndex<Task>>(seed: usize) -> Vec<(usize, bool)> {
+        use super::{BlockingQueue, FailurePolicy};
+        use crate::AbstractPriorityQueue;
+
+        let mut next_random = seeded_random(seed);
+        let mut q = BlockingQueue::<Task, usize, D>::default();
+        q.change_failure_policy(FailurePolicy::CancelDependents);
+        let mut leases = Vec::new();
+        let mut out = Vec::new();
+        for id in 0..120 {
+            let mut reads = Vec::new();
+            let mut writes = Vec::new();
+            for key in 0..4 {
+                match next_random(6) {
+                    0 => writes.push(key),
+                    1 => reads.push(key),
+                    2 => {
+                        reads.push(key);
+                        writes.push(key);
+                    }
+                    _ => {}
+                }
+            }
+            q.my_enqueue(Task { id, reads, writes }, (id * 7919) % 1009);
+            match next_random(4) {
+                0 => leases.extend(q.lease().map(|(lease, _, _)| lease)),
+                1 if !leases.is_empty() => {
+                    let (done, _) = q.complete(leases.remove(0)).expect("it was leased");
+                    out.push((done.id, false));
+                }
+                2 if !leases.is_empty() => {
+                    let which = next_random(leases.len());
+                    let mut cancelled: Vec<_> = q
+                        .fail(leases.remove(which))
+                        .expect("it was leased")
+                        .into_iter()
+                        .map(|(z, _)| (z.id, true))
+                        .collect();
+                    cancelled.sort_unstable();
+                    out.extend(cancelled);
+                }
+                _ => {}
+            }
+        }
+        for lease in leases {
+            let (done, _) = q.complete(lease).expect("it was leased");
+            out.push((done.id, false));
+        }
+        out.extend(q.drain_all().into_iter().map(|(z, _)| (z.id, false)));
+        out
+    }
+
+    /// the last writer of key 1 is cancelled along with what it blocks
+    /// then the next writer and reader of key 1 come in
+    #[allow(dead_code)]
+    fn after_cancelling_writer<D: super::DependencyIndex<Task>>() -> Vec<usize> {
+        use super::{BlockingQueue, FailurePolicy};
+        use crate::AbstractPriorityQueue;
+
+        let mut q = BlockingQueue::<Task, usize, D>::default();
+        q.change_failure_policy(FailurePolicy::CancelDependents);
+        q.my_enqueue(task(0, &[], &[0]), 9);
+        q.my_enqueue(task(1, &[], &[1]), 1);
+        q.my_enqueue(task(2, &[1], &[]), 2);
+        q.my_enqueue(task(3, &[0], &[1]), 3);
+        let (lease_0, _, _) = q.lease().expect("two are ready");
+        let cancelled: Vec<_> = q
+            .fail(lease_0)
+            .expect("it was leased")
+            .into_iter()
+            .map(|(z, _)| z.id)
+            .collect();
+        assert_eq!(cancelled, vec![0, 3]);
+        // still has to wait for 1 and the reader 2 that 3 was waiting for
+        q.my_enqueue(task(4, &[], &[1]), 8);
+        q.my_enqueue(task(5, &[1], &[]), 7);
+        q.drain_all().into_iter().map(|(z, _)| z.id).collect()
+    }
+
+    #[test]
+    fn keyed_same_as_pairwise_after_cancelling() {
+        use super::{KeyedDependencies, Pairwise};
+
+        assert_eq!(after_cancelling_writer::<Pairwise>(), vec![1, 2, 4, 5]);
+        assert_eq!(
+            after_cancelling_writer::<KeyedDependencies<u8>>(),
+            vec![1, 2, 4, 5]
+        );
+        for seed in 0..20 {
+            let pairwise = random_failures::<Pairwise>(seed);
+            assert_eq!(pairwise.len(), 120);
+            assert!(pairwise.iter().any(|(_, cancelled)| *cancelled));
+            assert_eq!(pairwise, random_failures::<KeyedDependencies<u8>>(seed));
+        }
+    }
+
+    /// a step that has to come after some others whichever is enqueued first
+    #[allow(dead_code)]
+    #[derive(Debug, PartialEq, Eq)]
+    struct Step {
+        name: usize,
+        after: Vec<usize>,
+    }
+
+    impl super::Blocker for Step {
+        fn am_i_blocker(&self, other: &Self) -> bool {
+            other.after.contains(&self.name)
+        }
+    }
+
+    #[test]
+    fn inconsistencies() {
+        use super::{BlockingQueue, InconsistencyKind, Validation};
+        use crate::AbstractPriorityQueue;
+
+        let step = |name, after: &[usize]| Step {
+            name,
+            after: after.to_vec(),
+        };
+
+        // each after the other
+        let mut q = BlockingQueue::<Step, usize>::default();
+        q.change_validation(Validation::Symmetric);
+        q.my_enqueue(step(0, &[1]), 0);
+        let e = q.try_enqueue(step(1, &[0]), 3).unwrap_err();
+        let InconsistencyKind::Symmetric(other) = e.kind else {
+            panic!("expected symmetric blockers");
+        };
+        assert_eq!(q.item_at(other).map(|(z, _)| z.name), Some(0));
+        assert_eq!((e.item.name, e.priority), (1, 3));
+        assert_eq!(
+            e.to_string(),
+            "item with priority 3 and node 0 both claim to block the other"
+        );
+        assert_eq!(q.my_len(), 1);
+
+        // 0 after 2 after 1 after 0
+        let mut q = BlockingQueue::<Step, usize>::default();
+        q.change_validation(Validation::Cycles);
+        q.my_enqueue(step(0, &[2]), 0);
+        q.my_enqueue(step(1, &[0]), 1);
+        let e = q.try_enqueue(step(2, &[1]), 2).unwrap_err();
+        let InconsistencyKind::Cycle(cycle) = e.kind else {
+            panic!("expected a cycle");
+        };
+        let cycle: Vec<_> = cycle
+            .into_iter()
+            .map(|z| q.item_at(z).map(|(z, _)| z.name))
+            .collect();
+        assert_eq!(cycle, vec![Some(0), Some(1)]);
+        assert_eq!(q.my_len(), 2);
+        assert!(q.try_enqueue(step(2, &[]), 2).is_ok());
+        let all_out: Vec<_> = q.drain_all().into_iter().map(|(z, _)| z.name).collect();
+        assert_eq!(all_out, vec![2, 0, 1]);
+
+        // checking never changes the order, a later step can only hold back one already waiting
+        // when blocking is bidirectional, which rejects cycles even without validation
+        for (validation, bidirectional_blocking, expected) in [
+            (Validation::Off, false, vec![0, 1]),
+            (Validation::Symmetric, false, vec![0, 1]),
+            (Validation::Cycles, false, vec![0, 1]),
+            (Validation::Off, true, vec![1, 0]),
+            (Validation::Cycles, true, vec![1, 0]),
+        ] {
+            let mut q = BlockingQueue::<Step, usize>::default();
+            q.change_validation(validation);
+            q.change_bidirectional_blocking(bidirectional_blocking);
+            q.my_enqueue(step(0, &[1]), 9);
+            q.my_enqueue(step(1, &[]), 0);
+            let all_out: Vec<_> = q.drain_all().into_iter().map(|(z, _)| z.name).collect();
+            assert_eq!(all_out, expected, "{validation:?} {bidirectional_blocking}");
+        }
+        let mut q = BlockingQueue::<Step, usize>::default();
+        q.change_bidirectional_blocking(true);
+        q.my_enqueue(step(0, &[2]), 0);
+        q.my_enqueue(step(1, &[0]), 1);
+        let e = q.try_enqueue(step(2, &[1]), 2).unwrap_err();
+        assert!(matches!(e.kind, InconsistencyKind::Cycle(_)));
+    }
+
+    #[test]
+    fn priority_inheritance() {
+        use super::{BlockingQueue, FailurePolicy, KeyedDependencies};
+        use crate::AbstractPriorityQueue;
+
+        let ids = |q: &mut BlockingQueue<Task, usize, KeyedDependencies<u8>>| -> Vec<usize> {
+            q.drain_all().into_iter().map(|(z, _)| z.id).collect()
+        };
+
+        // the urgent reader is stuck behind the unimportant writer
+        let mut q = BlockingQueue::<Task, usize, KeyedDependencies<u8>>::default();
+        q.my_enqueue(task(0, &[], &[1]), 1);
+        q.my_enqueue(task(1, &[3], &[]), 5);
+        q.my_enqueue(task(2, &[1], &[]), 9);
+        assert_eq!(q.my_peek().map(|(z, w)| (z.id, *w)), Some((1, 5)));
+        q.change_priority_inheritance(Some(|p1, p2| *p1.max(p2)));
+        // ordered by what it inherited but still with its own priority
+        assert_eq!(q.my_peek().map(|(z, w)| (z.id, *w)), Some((0, 1)));
+        assert_eq!(ids(&mut q), vec![0, 2, 1]);
+
+        // kept up to date while enqueueing
+        q.my_enqueue(task(3, &[], &[1]), 1);
+        q.my_enqueue(task(4, &[3], &[]), 5);
+        assert_eq!(q.my_peek().map(|(z, _)| z.id), Some(4));
+        q.my_enqueue(task(5, &[1], &[2]), 0);
+        q.my_enqueue(task(6, &[2], &[]), 9);
+        assert_eq!(q.my_peek().map(|(z, _)| z.id), Some(3));
+        assert_eq!(ids(&mut q), vec![3, 5, 6, 4]);
+
+        // and while cancelling
+        q.change_failure_policy(FailurePolicy::CancelDependents);
+        q.my_enqueue(task(7, &[], &[1]), 0);
+        let (lease_7, _, _) = q.lease().expect("it is ready");
+        q.my_enqueue(task(8, &[], &[2]), 1);
+        q.my_enqueue(task(9, &[1, 2], &[]), 9);
+        q.my_enqueue(task(10, &[4], &[]), 5);
+        assert_eq!(q.my_peek().map(|(z, _)| z.id), Some(8));
+        assert_eq!(q.fail(lease_7).map(|z| z.len()), Some(2));
+        assert_eq!(ids(&mut q), vec![10, 8]);
+
+        // some other combination
+        q.change_priority_inheritance(Some(|p1, p2| p1 + p2));
+        q.my_enqueue(task(11, &[], &[1]), 1);
+        q.my_enqueue(task(12, &[1], &[]), 3);
+        q.my_enqueue(task(13, &[1], &[]), 2);
+        q.my_enqueue(task(14, &[5], &[]), 5);
+        assert_eq!(q.my_peek().map(|(z, _)| z.id), Some(11));
+        q.change_priority_inheritance(None);
+        assert_eq!(ids(&mut q), vec![14, 11, 12, 13]);
+    }
+
+    #[test]
+    fn exports() {
+        use super::{BlockingQueue, KeyedDependencies};
+        use crate::AbstractPriorityQueue;
+        use petgraph::stable_graph::NodeIndex;
+
+        let mut q = BlockingQueue::<Task, usize, KeyedDependencies<u8>>::default();
+        let nodes: Vec<_> = [
+            (task(0, &[], &[1]), 1),
+            (task(1, &[1], &[2]), 2),
+            (task(2, &[2], &[]), 3),
+            (task(3, &[9], &[]), 4),
+        ]
+        .into_iter()
+        .map(|(z, w)| q.try_enqueue(z, w).expect("no validation"))
+        .collect();
+        let (lease_3, _, _) = q.lease().expect("two are ready");
+
+        let chain: Vec<_> = q
+            .why_blocked(nodes[2])
+            .expect("it is waiting")
+            .into_iter()
+            .map(|(z, w)| (z.id, *w))
+            .collect();
+        assert_eq!(chain, vec![(0, 1), (1, 2)]);
+        assert_eq!(q.why_blocked(nodes[0]), Some(vec![]));
+        assert_eq!(q.why_blocked(nodes[3]), Some(vec![]));
+        assert!(q.why_blocked(NodeIndex::new(4)).is_none());
+
+        let label = |z: &Task| format!("task \"{}\"", z.id);
+        assert_eq!(
+            q.to_dot(label),
+            "digraph {
+    0 [label=\"task \\\"0\\\"\\npriority 1\", style=filled, fillcolor=palegreen]
+    1 [label=\"task \\\"1\\\"\\npriority 2\"]
+    2 [label=\"task \\\"2\\\"\\npriority 3\", peripheries=2]
+    3 [label=\"task \\\"3\\\"\\npriority 4\", style=filled, fillcolor=lightblue, peripheries=2]
+    0 -> 1
+    1 -> 2
+}
+"
+        );
+        assert_eq!(
+            q.to_json(label),
+            concat!(
+                "{\"nodes\": [",
+                "{\"id\": 0, \"label\": \"task \\\"0\\\"\", \"priority\": \"1\", \"source\": true, \"sink\": false, \"in_flight\": false}, ",
+                "{\"id\": 1, \"label\": \"task \\\"1\\\"\", \"priority\": \"2\", \"source\": false, \"sink\": false, \"in_flight\": false}, ",
+                "{\"id\": 2, \"label\": \"task \\\"2\\\"\", \"priority\": \"3\", \"source\": false, \"sink\": true, \"in_flight\": false}, ",
+                "{\"id\": 3, \"label\": \"task \\\"3\\\"\", \"priority\": \"4\", \"source\": true, \"sink\": true, \"in_flight\": true}",
+                "], \"edges\": [{\"from\": 0, \"to\": 1}, {\"from\": 1, \"to\": 2}]}"
+            )
+        );
+        // the same item again is told apart by its node
+        let again = q.try_enqueue(task(0, &[], &[1]), 5).expect("no validation");
+        let chain: Vec<_> = q
+            .why_blocked(again)
+            .expect("it is waiting")
+            .into_iter()
+            .map(|(z, w)| (z.id, *w))
+            .collect();
+        assert_eq!(chain, vec![(0, 1)]);
+        assert_eq!(q.why_blocked(nodes[0]), Some(vec![]));
+        q.drain_all();
+        q.complete(lease_3);
+        assert_eq!(q.to_json(label), "{\"nodes\": [], \"edges\": []}");
     }
 }
@@ -0,0 +1,136 @@
+use crate::my_priority_queue::{AbstractPriorityQueue, GivesBackPriorities};
+
+/// one of two kinds of `AbstractPriorityQueue` for the same items and priorities
+/// decided at runtime
+/// for example so the buckets of one `BucketQueue` can use different disciplines
+/// nest them to get more than two kinds
+pub enum EitherQueue<L, R> {
+    Left(L),
+    Right(R),
+}
+
+impl<T, P, L, R> AbstractPriorityQueue<T, P> for EitherQueue<L, R>
+where
+    P: Ord,
+    L: AbstractPriorityQueue<T, P>,
+    R: AbstractPriorityQueue<T, P>,
+{
+    fn empty_copy(&self) -> Self {
+        match self {
+            Self::Left(q) => Self::Left(q.empty_copy()),
+            Self::Right(q) => Self::Right(q.empty_copy()),
+        }
+    }
+
+    fn my_peek(&self) -> Option<(&T, &P)> {
+        match self {
+            Self::Left(q) => q.my_peek(),
+            Self::Right(q) => q.my_peek(),
+        }
+    }
+
+    fn my_enqueue(&mut self, new_obj: T, new_obj_priority: P) {
+        match self {
+            Self::Left(q) => q.my_enqueue(new_obj, new_obj_priority),
+            Self::Right(q) => q.my_enqueue(new_obj, new_obj_priority),
+        }
+    }
+
+    fn enqueue_batch(&mut self, new_batch: impl IntoIterator<Item = T>, new_batch_priority: P) {
+        match self {
+            Self::Left(q) => q.enqueue_batch(new_batch, new_batch_priority),
+            Self::Right(q) => q.enqueue_batch(new_batch, new_batch_priority),
+        }
+    }
+
+    fn my_dequeue(&mut self) -> Option<(T, P)> {
+        match self {
+            Self::Left(q) => q.my_dequeue(),
+            Self::Right(q) => q.my_dequeue(),
+        }
+    }
+
+    fn dequeue_batch(&mut self, around_how_many: usize, hard_limit: usize) -> Vec<(T, P)> {
+        match self {
+            Self::Left(q) => q.dequeue_batch(around_how_many, hard_limit),
+            Self::Right(q) => q.dequeue_batch(around_how_many, hard_limit),
+        }
+    }
+
+    fn my_len(&self) -> usize {
+        match self {
+            Self::Left(q) => q.my_len(),
+            Self::Right(q) => q.my_len(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match self {
+            Self::Left(q) => q.is_empty(),
+            Self::Right(q) => q.is_empty(),
+        }
+    }
+}
+
+impl<T, P, L, R> GivesBackPriorities<T, P> for EitherQueue<L, R>
+where
+    P: Ord,
+    L: GivesBackPriorities<T, P>,
+    R: GivesBackPriorities<T, P>,
+{
+}
+
+mod test {
+    use crate::Friendly;
+
+    const MY_U8_FREINDLINESS: u8 = 5;
+
+    #[allow(dead_code)]
+    #[derive(PartialEq, Eq, Debug)]
+    #[repr(transparent)]
+    struct MyU8(u8);
+    impl Friendly<u8> for MyU8 {
+        fn friendship_shibboleth(&self) -> u8 {
+            self.0 % MY_U8_FREINDLINESS
+        }
+    }
+
+    #[test]
+    fn mixed_buckets() {
+        use super::EitherQueue;
+        use crate::my_priority_queue::AbstractPriorityQueue;
+        use crate::{BucketQueue, IsraeliPriority, NoPriorityQueue};
+        use std::collections::{BTreeSet, HashMap};
+        use std::sync::Arc;
+
+        type Bucket = EitherQueue<NoPriorityQueue<MyU8, u8>, IsraeliPriority<MyU8, u8, u8>>;
+        let mut q = BucketQueue::<MyU8, u8, u8, Bucket, HashMap<u8, Bucket>, BTreeSet<u8>, _>::with_coarse_grainer(
+            0,
+            0,
+            &EitherQueue::Left(NoPriorityQueue::new()),
+            |p: &u8| *p / 10,
+        );
+        // bulk work below 10 goes first in first out, interactive work travels with its friends
+        q.change_bucket_factory(Some(Arc::new(|c| {
+            if *c == 0 {
+                EitherQueue::Left(NoPriorityQueue::new())
+            } else {
+                EitherQueue::Right(IsraeliPriority::with_capacity(4))
+            }
+        })));
+        let mut q2 = q.empty_copy();
+        for q in [&mut q, &mut q2] {
+            q.my_enqueue(MyU8(3), 1);
+            q.my_enqueue(MyU8(4), 7);
+            q.my_enqueue(MyU8(1), 20);
+            q.my_enqueue(MyU8(2), 30);
+            q.my_enqueue(MyU8(6), 20);
+            q.my_enqueue(MyU8(7), 25);
+            assert_eq!(q.my_len(), 6);
+            let all_out: Vec<_> = q.drain_all().into_iter().map(|(z, _)| z.0).collect();
+            assert_eq!(&all_out[0..2], &[2, 7]);
+            assert!(all_out[2..4].contains(&1) && all_out[2..4].contains(&6));
+            assert_eq!(&all_out[4..], &[3, 4]);
+        }
+    }
+}
@@ -0,0 +1,607 @@
+use nonempty::NonEmpty;
+use priority_queue::PriorityQueue;
+use std::{cmp::Ordering, collections::HashMap, hash::Hash, marker::PhantomData};
+
+use crate::israeli_priority::FoyerPreemption;
+
+/// how the priorities of friends combine
+/// whether the priority changed and what it is now
+pub type PriorityCombiner<P> = fn(&P, &P) -> (bool, P);
+
+/// just picks the bigger of the two
+/// that way a high priority member joining a group in line, can boost
+/// their priority and move that entire group up even further
+pub(crate) fn default_combiner<P: Ord + Clone>(p1: &P, p2: &P) -> (bool, P) {
+    if p1.cmp(p2) == Ordering::Less {
+        (true, p2.clone())
+    } else {
+        (false, p1.clone())
+    }
+}
+
+/// how the friend groups at one level behave
+#[derive(Clone)]
+pub(crate) struct LevelPolicy<P> {
+    pub(crate) priority_combiner: PriorityCombiner<P>,
+    pub(crate) foyer_preemption: FoyerPreemption<P>,
+}
+
+impl<P: Ord + Clone> Default for LevelPolicy<P> {
+    fn default() -> Self {
+        Self {
+            priority_combiner: default_combiner,
+            foyer_preemption: FoyerPreemption::Never,
+        }
+    }
+}
+
+/// split items paired with their shibboleth into friend groups
+/// in the order each group first appears
+/// when they are all friends there is only one group and nothing is hashed
+pub(crate) fn partition_friends<K: Hash + Eq + Clone, T>(
+    with_shibboleths: NonEmpty<(K, T)>,
+) -> Vec<(K, NonEmpty<T>)> {
+    let all_friends = with_shibboleths
+        .tail
+        .iter()
+        .all(|(r, _)| *r == with_shibboleths.head.0);
+    if all_friends {
+        let (the_shibboleth, head_friend) = with_shibboleths.head;
+        let mut new_batch = NonEmpty::singleton(head_friend);
+        new_batch.extend(with_shibboleths.tail.into_iter().map(|(_, z)| z));
+        return vec![(the_shibboleth, new_batch)];
+    }
+    let mut partition_idx: HashMap<K, usize> = HashMap::new();
+    let mut partitions: Vec<(K, NonEmpty<T>)> = Vec::new();
+    for (my_shibboleth, z) in with_shibboleths {
+        if let Some(idx) = partition_idx.get(&my_shibboleth) {
+            partitions[*idx].1.push(z);
+        } else {
+            partition_idx.insert(my_shibboleth.clone(), partitions.len());
+            partitions.push((my_shibboleth, NonEmpty::singleton(z)));
+        }
+    }
+    partitions
+}
+
+/// splits off the policy for the outermost of `policies` from the ones below it
+pub(crate) fn split_policies<P>(
+    policies: &[LevelPolicy<P>],
+) -> (&LevelPolicy<P>, &[LevelPolicy<P>]) {
+    policies
+        .split_first()
+        .expect("there is a policy for every level")
+}
+
+/// what `FriendLevel` keeps for each friend group
+/// `Context` is whatever making and going through a group needs besides its items
+/// and `rest_of_path` is whatever comes after the shibboleth of the level holding the group
+pub(crate) trait GroupOfFriends<T, P, H>: Sized {
+    type Context: ?Sized;
+
+    /// a new group for `new_batch`
+    fn new_group(
+        rest_of_path: &[H],
+        new_batch: NonEmpty<T>,
+        new_batch_priority: &P,
+        context: &Self::Context,
+    ) -> Self;
+
+    /// more friends join this group
+    fn enqueue(
+        &mut self,
+        rest_of_path: &[H],
+        new_batch: NonEmpty<T>,
+        new_batch_priority: P,
+        context: &Self::Context,
+    );
+
+    fn len(&self) -> usize;
+
+    /// what would `dequeue` give
+    /// `group_priority` is the priority this group has at the level above
+    fn peek<'a>(&'a self, group_priority: &'a P, context: &Self::Context)
+        -> Option<(&'a T, &'a P)>;
+
+    /// one item comes out along with what is left of this group if anything
+    fn dequeue(self, group_priority: &P, context: &Self::Context) -> ((T, P), Option<Self>);
+
+    /// is there an innermost group partway through going in
+    fn mid_friend_group(&self) -> bool;
+}
+
+/// at the innermost level the group is the friends themselves
+/// otherwise it is split further into smaller friend groups
+pub(crate) enum FriendGroup<T, P, H>
+where
+    P: Ord,
+    H: Hash + Eq,
+{
+    Friends(NonEmpty<T>),
+    Subgroups(Box<FriendLevel<T, P, H>>),
+}
+
+/// one level of friend groups
+/// a priority queue of the shibboleths waiting in line,
+/// how those shibboleths translate to their groups
+/// and the group in the ``foyer`` which is currently going in
+/// `policy` is the one for this level and `context` is passed down to the groups
+/// which by default are `FriendGroup`
+pub(crate) struct FriendLevel<T, P, H, G = FriendGroup<T, P, H>>
+where
+    P: Ord,
+    H: Hash + Eq,
+{
+    underlying: PriorityQueue<H, P>,
+    current_friend_group: Option<(H, G, P)>,
+    shibboleth_to_friends: HashMap<H, G>,
+    waiting_len: usize,
+    junk: PhantomData<T>,
+}
+
+/// the policies for the levels below come along as the context
+impl<T, P, H> GroupOfFriends<T, P, H> for FriendGroup<T, P, H>
+where
+    P: Ord + Clone,
+    H: Hash + Eq + Clone,
+{
+    type Context = [LevelPolicy<P>];
+
+    /// with however many levels are left in `rest_of_path`
+    fn new_group(
+        rest_of_path: &[H],
+        new_batch: NonEmpty<T>,
+        new_batch_priority: &P,
+        policies: &[LevelPolicy<P>],
+    ) -> Self {
+        match rest_of_path.split_first() {
+            None => Self::Friends(new_batch),
+            Some((next_shibboleth, rest_of_path)) => {
+                let (next_policy, deeper_policies) = split_policies(policies);
+                let mut sublevel = FriendLevel::with_capacity(0);
+                sublevel.enqueue_friends(
+                    next_shibboleth,
+                    rest_of_path,
+                    new_batch,
+                    new_batch_priority.clone(),
+                    next_policy,
+                    deeper_policies,
+                );
+                Self::Subgroups(Box::new(sublevel))
+            }
+        }
+    }
+
+    fn enqueue(
+        &mut self,
+        rest_of_path: &[H],
+        new_batch: NonEmpty<T>,
+        new_batch_priority: P,
+        policies: &[LevelPolicy<P>],
+    ) {
+        match (self, rest_of_path.split_first()) {
+            (Self::Friends(friends), None) => friends.extend(new_batch),
+            (Self::Subgroups(sublevel), Some((next_shibboleth, rest_of_path))) => {
+                let (next_policy, deeper_policies) = split_policies(policies);
+                sublevel.enqueue_friends(
+                    next_shibboleth,
+                    rest_of_path,
+                    new_batch,
+                    new_batch_priority,
+                    next_policy,
+                    deeper_policies,
+                );
+            }
+            _ => panic!("all friendship paths in the queue should be the same length"),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::Friends(friends) => friends.len(),
+            Self::Subgroups(sublevel) => sublevel.len(),
+        }
+    }
+
+    fn peek<'a>(
+        &'a self,
+        group_priority: &'a P,
+        policies: &[LevelPolicy<P>],
+    ) -> Option<(&'a T, &'a P)> {
+        match self {
+            // `dequeue` takes the last of the group first
+            Self::Friends(friends) => Some((friends.last(), group_priority)),
+            Self::Subgroups(sublevel) => {
+                let (next_policy, deeper_policies) = split_policies(policies);
+                sublevel.peek(next_policy, deeper_policies)
+            }
+        }
+    }
+
+    /// with the priority of the innermost group it was in
+    fn dequeue(self, group_priority: &P, policies: &[LevelPolicy<P>]) -> ((T, P), Option<Self>) {
+        match self {
+            Self::Friends(mut friends) => {
+                if friends.len() == 1 {
+                    ((friends.head, group_priority.clone()), None)
+                } else {
+                    let last_friend = friends.pop().expect("Already checked length");
+                    (
+                        (last_friend, group_priority.clone()),
+                        Some(Self::Friends(friends)),
+                    )
+                }
+            }
+            Self::Subgroups(mut sublevel) => {
+                let (next_policy, deeper_policies) = split_policies(policies);
+                let ret_val = sublevel
+                    .dequeue(next_policy, deeper_policies)
+                    .expect("empty subgroups are never kept around");
+                if sublevel.is_empty() {
+                    (ret_val, None)
+                } else {
+                    (ret_val, Some(Self::Subgroups(sublevel)))
+                }
+            }
+        }
+    }
+
+    fn mid_friend_group(&self) -> bool {
+        match self {
+            Self::Friends(_) => true,
+            Self::Subgroups(sublevel) => sublevel.mid_friend_group(),
+        }
+    }
+}
+
+impl<T, P, H> FriendGroup<T, P, H>
+where
+    P: Ord + Clone,
+    H: Hash + Eq + Clone,
+{
+    /// all of the items in this group
+    /// the innermost groups keep the order they joined in
+    /// and they come ``foyer`` first then by the priorities they have waiting in line
+    pub(crate) fn into_items(self) -> NonEmpty<T> {
+        match self {
+            Self::Friends(friends) => friends,
+            Self::Subgroups(sublevel) => sublevel
+                .into_items()
+                .expect("empty subgroups are never kept around"),
+        }
+    }
+}
+
+impl<T, P, H, G> FriendLevel<T, P, H, G>
+where
+    P: Ord + Clone,
+    H: Hash + Eq + Clone,
+    G: GroupOfFriends<T, P, H>,
+{
+    /// setup with capacity for specified number of distinct friend groups
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            underlying: PriorityQueue::with_capacity(capacity),
+            current_friend_group: None,
+            shibboleth_to_friends: HashMap::with_capacity(capacity >> 3),
+            waiting_len: 0,
+            junk: PhantomData,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        let at_head_len = self
+            .current_friend_group
+            .as_ref()
+            .map_or(0, |(_, head_group, _)| head_group.len());
+        at_head_len + self.waiting_len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.waiting_len == 0 && self.current_friend_group.is_none()
+    }
+
+    /// does the head of the waiting line get to push aside
+    /// the group currently in the ``foyer``
+    fn foyer_preempted(&self, foyer_preemption: &FoyerPreemption<P>) -> bool {
+        match (&self.current_friend_group, self.underlying.peek()) {
+            (Some((_, _, foyer_priority)), Some((_, waiting_priority))) => {
+                foyer_preemption.preempts(waiting_priority, foyer_priority)
+            }
+            _ => false,
+        }
+    }
+
+    /// if the head of the waiting line preempts the ``foyer``
+    /// send the ``foyer`` group back into the line whole
+    pub(crate) fn apply_foyer_preemption(&mut self, foyer_preemption: &FoyerPreemption<P>) {
+        if self.foyer_preempted(foyer_preemption) {
+            if let Some((the_shibboleth, foyer_group, foyer_priority)) =
+                self.current_friend_group.take()
+            {
+                self.put_back_in_line(the_shibboleth, foyer_group, foyer_priority);
+            }
+        }
+    }
+
+    /// a group of friends all with `the_shibboleth` at this level join
+    /// either with their friends waiting in line, with their friends in the ``foyer``
+    /// or as a new group at the back of the line
+    pub(crate) fn enqueue_friends(
+        &mut self,
+        the_shibboleth: &H,
+        rest_of_path: &[H],
+        new_batch: NonEmpty<T>,
+        new_batch_priority: P,
+        my_policy: &LevelPolicy<P>,
+        context: &G::Context,
+    ) {
+        let new_len = new_batch.len();
+        if let Some(old_priority) = self.underlying.get_priority(the_shibboleth) {
+            // found friends waiting in line
+            let (priority_changed, new_priority) =
+                (my_policy.priority_combiner)(old_priority, &new_batch_priority);
+            if priority_changed {
+                // the priority can make this group the head of the waiting
+                // but it can't push them up to be past the group that is currently entering
+                // they are already in the ``foyer``
+                let _overwritten = self
+                    .underlying
+                    .change_priority(the_shibboleth, new_priority);
+            }
+            self.shibboleth_to_friends
+                .get_mut(the_shibboleth)
+                .expect("all shibboleths in priority queue have corresponding items")
+                .enqueue(rest_of_path, new_batch, new_batch_priority, context);
+            self.waiting_len += new_len;
+        } else if let Some((_, head_group, head_priority)) = self
+            .current_friend_group
+            .as_mut()
+            .filter(|(head_shibboleth, _, _)| head_shibboleth == the_shibboleth)
+        {
+            // they are your friends and they are the ones just going in now
+            let (priority_changed, new_priority) =
+                (my_policy.priority_combiner)(head_priority, &new_batch_priority);
+            if priority_changed {
+                *head_priority = new_priority;
+            }
+            head_group.enqueue(rest_of_path, new_batch, new_batch_priority, context);
+        } else {
+            // no friends in line or in the ``foyer``, go to the back of the line
+            // even if you have higher priority than who is in the ``foyer``
+            let new_group = G::new_group(rest_of_path, new_batch, &new_batch_priority, context);
+            self.underlying
+                .push(the_shibboleth.clone(), new_batch_priority);
+            self.shibboleth_to_friends
+                .insert(the_shibboleth.clone(), new_group);
+            self.waiting_len += new_len;
+        }
+    }
+
+    /// a whole group that was taken out goes back into the line
+    /// nobody else in line can have their shibboleth, because their friends would have joined them
+    pub(crate) fn put_back_in_line(&mut self, the_shibboleth: H, friend_grp: G, group_priority: P) {
+        self.waiting_len += friend_grp.len();
+        self.underlying.push(the_shibboleth.clone(), group_priority);
+        self.shibboleth_to_friends
+            .insert(the_shibboleth, friend_grp);
+    }
+
+    /// a whole group that was taken out goes back into the ``foyer``
+    pub(crate) fn put_in_foyer(&mut self, the_shibboleth: H, friend_grp: G, group_priority: P) {
+        self.current_friend_group = Some((the_shibboleth, friend_grp, group_priority));
+    }
+
+    /// the entire group currently in the ``foyer``
+    pub(crate) fn take_foyer_group(&mut self) -> Option<(H, G, P)> {
+        self.current_friend_group.take()
+    }
+
+    /// the entire group at the head of the waiting line
+    pub(crate) fn pop_waiting_group(&mut self) -> Option<(H, G, P)> {
+        let (the_shibboleth, group_priority) = self.underlying.pop()?;
+        let head_of_line = self
+            .shibboleth_to_friends
+            .remove(&the_shibboleth)
+            .expect("all shibboleths in priority queue have corresponding items");
+        self.waiting_len -= head_of_line.len();
+        Some((the_shibboleth, head_of_line, group_priority))
+    }
+
+    pub(crate) fn peek(
+        &self,
+        my_policy: &LevelPolicy<P>,
+        context: &G::Context,
+    ) -> Option<(&T, &P)> {
+        let foyer_group = if self.foyer_preempted(&my_policy.foyer_preemption) {
+            None
+        } else {
+            self.current_friend_group.as_ref()
+        };
+        match foyer_group {
+            Some((_, head_group, head_priority)) => head_group.peek(head_priority, context),
+            None => self.underlying.peek().and_then(|(gp, p)| {
+                self.shibboleth_to_friends
+                    .get(gp)
+                    .expect("all shibboleths in priority queue have corresponding items")
+                    .peek(p, context)
+            }),
+        }
+    }
+
+    pub(crate) fn dequeue(
+        &mut self,
+        my_policy: &LevelPolicy<P>,
+        context: &G::Context,
+    ) -> Option<(T, P)> {
+        self.apply_foyer_preemption(&my_policy.foyer_preemption);
+        // if nobody is in the ``foyer``, the next group becomes the currently processing group
+        let (head_shibboleth, head_group, head_priority) = self
+            .take_foyer_group()
+            .or_else(|| self.pop_waiting_group())?;
+        let (ret_val, rest_of_group) = head_group.dequeue(&head_priority, context);
+        if let Some(rest_of_group) = rest_of_group {
+            self.current_friend_group = Some((head_shibboleth, rest_of_group, head_priority));
+        }
+        Some(ret_val)
+    }
+
+    /// is there an innermost group partway through going in
+    pub(crate) fn mid_friend_group(&self) -> bool {
+        self.current_friend_group
+            .as_ref()
+            .is_some_and(|(_, head_group, _)| head_group.mid_friend_group())
+    }
+
+    /// take the entire group with this shibboleth out of the queue
+    /// whether they are waiting in line or in the ``foyer``
+    pub(crate) fn remove_group(&mut self, which: &H) -> Option<(G, P)> {
+        if let Some((_, group_priority)) = self.underlying.remove(which) {
+            let friend_grp = self
+                .shibboleth_to_friends
+                .remove(which)
+                .expect("all shibboleths in priority queue have corresponding items");
+            self.waiting_len -= friend_grp.len();
+            Some((friend_grp, group_priority))
+        } else if self.is_in_foyer(which) {
+            self.current_friend_group
+                .take()
+                .map(|(_, foyer_group, foyer_priority)| (foyer_group, foyer_priority))
+        } else {
+            None
+        }
+    }
+
+    /// overwrite the priority of the entire group with this shibboleth
+    /// without going through the combiner, giving back the old priority
+    pub(crate) fn set_group_priority(&mut self, which: &H, new_priority: P) -> Option<P> {
+        if self.underlying.get_priority(which).is_some() {
+            self.underlying.change_priority(which, new_priority)
+        } else if self.is_in_foyer(which) {
+            self.current_friend_group
+                .as_mut()
+                .map(|(_, _, foyer_priority)| std::mem::replace(foyer_priority, new_priority))
+        } else {
+            None
+        }
+    }
+}
+
+impl<T, P, H> FriendLevel<T, P, H>
+where
+    P: Ord + Clone,
+    H: Hash + Eq + Clone,
+{
+    /// all of the items at this level, see `FriendGroup::into_items`
+    fn into_items(self) -> Option<NonEmpty<T>> {
+        let Self {
+            underlying,
+            current_friend_group,
+            mut shibboleth_to_friends,
+            ..
+        } = self;
+        let mut groups = current_friend_group
+            .map(|(_, foyer_group, _)| foyer_group)
+            .into_iter()
+            .chain(
+                underlying
+                    .into_sorted_iter()
+                    .map(move |(the_shibboleth, _)| {
+                        shibboleth_to_friends
+                            .remove(&the_shibboleth)
+                            .expect("all shibboleths in priority queue have corresponding items")
+                    }),
+            );
+        let mut items = groups.next()?.into_items();
+        for friend_grp in groups {
+            items.extend(friend_grp.into_items());
+        }
+        Some(items)
+    }
+}
+
+/// read only queries about where friend groups at this level are in line
+/// these never change the queue
+impl<T, P, H, G> FriendLevel<T, P, H, G>
+where
+    P: Ord + Clone,
+    H: Hash + Eq + Clone,
+    G: GroupOfFriends<T, P, H>,
+{
+    pub(crate) fn num_waiting_groups(&self) -> usize {
+        self.underlying.len()
+    }
+
+    pub(crate) fn is_in_foyer(&self, which: &H) -> bool {
+        self.current_friend_group
+            .as_ref()
+            .is_some_and(|(foyer_shibboleth, _, _)| foyer_shibboleth == which)
+    }
+
+    pub(crate) fn group(&self, which: &H) -> Option<&G> {
+        if let Some(friend_grp) = self.shibboleth_to_friends.get(which) {
+            Some(friend_grp)
+        } else if self.is_in_foyer(which) {
+            self.current_friend_group
+                .as_ref()
+                .map(|(_, group, _)| group)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn group_len(&self, which: &H) -> Option<usize> {
+        self.group(which).map(G::len)
+    }
+
+    pub(crate) fn group_priority(&self, which: &H) -> Option<&P> {
+        if let Some(waiting_priority) = self.underlying.get_priority(which) {
+            Some(waiting_priority)
+        } else if self.is_in_foyer(which) {
+            self.current_friend_group.as_ref().map(|(_, _, p)| p)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn waiting_rank(&self, which: &H) -> Option<usize> {
+        let my_priority = self.underlying.get_priority(which)?;
+        Some(
+            self.underlying
+                .iter()
+                .filter(|(_, other_priority)| *other_priority > my_priority)
+                .count(),
+        )
+    }
+
+    pub(crate) fn items_ahead(
+        &self,
+        which: &H,
+        foyer_preemption: &FoyerPreemption<P>,
+    ) -> Option<usize> {
+        if self.is_in_foyer(which) {
+            return Some(0);
+        }
+        let my_priority = self.underlying.get_priority(which)?;
+        let mut ahead = 0;
+        let mut groups_ahead = 0;
+        for (other_shibboleth, other_priority) in &self.underlying {
+            if other_priority > my_priority {
+                groups_ahead += 1;
+                ahead += self
+                    .shibboleth_to_friends
+                    .get(other_shibboleth)
+                    .expect("all shibboleths in priority queue have corresponding items")
+                    .len();
+            }
+        }
+        if let Some((_, foyer_group, foyer_priority)) = &self.current_friend_group {
+            let would_preempt =
+                groups_ahead == 0 && foyer_preemption.preempts(my_priority, foyer_priority);
+            if !would_preempt {
+                ahead += foyer_group.len();
+            }
+        }
+        Some(ahead)
+    }
+}
@@ -0,0 +1,237 @@
+use crate::nested_queue::OccupancyIndex;
+
+const WORD_BITS: usize = 64;
+
+/// a set of `usize` stored as bits
+/// with more levels of bits on top, where each bit says if the 64 bits below it has any set
+/// so the highest or lowest element is found going down the levels
+/// in time logarithmic base 64 regardless of how spread out the elements are
+/// good for coarse grained priorities that are dense integers
+/// it takes a bit for every possible element up to the highest one ever inserted
+/// so a handful of elements spread over a very wide range is better off in a `BTreeSet`
+#[derive(Default, Clone, Debug)]
+pub struct HierarchicalBitmap {
+    /// `levels[0]` has the actual elements
+    /// and the last level is a single word
+    levels: Vec<Vec<u64>>,
+}
+
+impl HierarchicalBitmap {
+    #[must_use]
+    pub fn new() -> Self {
+        Self { levels: Vec::new() }
+    }
+
+    /// make sure `which` fits in the bottom level
+    /// and all the levels above are big enough for the bottom level
+    fn grow_to_fit(&mut self, which: usize) {
+        let words_needed = which / WORD_BITS + 1;
+        if self.levels.is_empty() {
+            self.levels.push(Vec::new());
+        }
+        if self.levels[0].len() >= words_needed {
+            return;
+        }
+        self.levels[0].resize(words_needed, 0);
+        let mut level = 0;
+        loop {
+            let below_len = self.levels[level].len();
+            if below_len == 1 {
+                // only one word so this is the top
+                self.levels.truncate(level + 1);
+                break;
+            }
+            let above_len = below_len.div_ceil(WORD_BITS);
+            if level + 1 == self.levels.len() {
+                // need a new level on top, made from what is already below
+                let mut new_level = vec![0; above_len];
+                for (idx, word) in self.levels[level].iter().enumerate() {
+                    if *word != 0 {
+                        new_level[idx / WORD_BITS] |= 1 << (idx % WORD_BITS);
+                    }
+                }
+                self.levels.push(new_level);
+            } else {
+                self.levels[level + 1].resize(above_len, 0);
+            }
+            level += 1;
+        }
+    }
+
+    pub fn insert(&mut self, which: usize) {
+        self.grow_to_fit(which);
+        let mut idx = which;
+        for level in &mut self.levels {
+            let word = &mut level[idx / WORD_BITS];
+            let was_empty = *word == 0;
+            *word |= 1 << (idx % WORD_BITS);
+            if !was_empty {
+                // the levels above already know about this word
+                break;
+            }
+            idx /= WORD_BITS;
+        }
+    }
+
+    pub fn remove(&mut self, which: usize) {
+        if self
+            .levels
+            .first()
+            .is_none_or(|bottom| bottom.len() <= which / WORD_BITS)
+        {
+            return;
+        }
+        let mut idx = which;
+        for level in &mut self.levels {
+            let word = &mut level[idx / WORD_BITS];
+            *word &= !(1 << (idx % WORD_BITS));
+            if *word != 0 {
+                // still something else in this word so the levels above stay the same
+                break;
+            }
+            idx /= WORD_BITS;
+        }
+    }
+
+    /// all the elements from lowest to highest
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.levels.first().into_iter().flat_map(|bottom| {
+            bottom.iter().enumerate().flat_map(|(idx, word)| {
+                (0..WORD_BITS)
+                    .filter(move |bit| word & (1 << bit) != 0)
+                    .map(move |bit| idx * WORD_BITS + bit)
+            })
+        })
+    }
+
+    #[must_use]
+    pub fn contains(&self, which: usize) -> bool {
+        self.levels.first().is_some_and(|bottom| {
+            bottom
+                .get(which / WORD_BITS)
+                .is_some_and(|word| word & (1 << (which % WORD_BITS)) != 0)
+        })
+    }
+
+    /// go down the levels, picking the bit with `pick_bit` each time
+    fn descend(&self, pick_bit: fn(u64) -> usize) -> Option<usize> {
+        let top = self.levels.last()?;
+        if top[0] == 0 {
+            return None;
+        }
+        let mut idx = 0;
+        for level in self.levels.iter().rev() {
+            let word = level[idx];
+            idx = idx * WORD_BITS + pick_bit(word);
+        }
+        Some(idx)
+    }
+
+    #[must_use]
+    pub fn max(&self) -> Option<usize> {
+        self.descend(|word| WORD_BITS - 1 - word.leading_zeros() as usize)
+    }
+
+    #[must_use]
+    pub fn min(&self) -> Option<usize> {
+        self.descend(|word| word.trailing_zeros() as usize)
+    }
+}
+
+impl OccupancyIndex<usize> for HierarchicalBitmap {
+    fn new() -> Self {
+        Self::new()
+    }
+
+    fn occupy(&mut self, which: &usize) {
+        self.insert(*which);
+    }
+
+    fn vacate(&mut self, which: &usize) {
+        self.remove(*which);
+    }
+
+    fn highest_occupied(&self) -> Option<usize> {
+        self.max()
+    }
+
+    fn lowest_occupied(&self) -> Option<usize> {
+        self.min()
+    }
+
+    fn all_occupied(&self) -> impl Iterator<Item = usize> + '_ {
+        self.iter()
+    }
+}
+
+mod test {
+
+    #[test]
+    fn matches_btreeset() {
+        use super::HierarchicalBitmap;
+        use std::collections::BTreeSet;
+
+        let mut bitmap = HierarchicalBitmap::new();
+        let mut expected = BTreeSet::new();
+        assert_eq!(bitmap.max(), None);
+        assert_eq!(bitmap.min(), None);
+        let mut step = 7usize;
+        for round in 0..2000 {
+            step = step.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            let which = (step >> 33) % [70, 5000, 300_000][round % 3];
+            if round % 4 == 3 {
+                bitmap.remove(which);
+                expected.remove(&which);
+                if let Some(lowest) = expected.first().copied() {
+                    bitmap.remove(lowest);
+                    expected.remove(&lowest);
+                }
+            } else {
+                bitmap.insert(which);
+                expected.insert(which);
+            }
+            assert_eq!(bitmap.max(), expected.last().copied());
+            assert_eq!(bitmap.min(), expected.first().copied());
+            assert!(bitmap.contains(which) == expected.contains(&which));
+        }
+        assert!(bitmap.iter().eq(expected.iter().copied()));
+        for which in expected.clone() {
+            bitmap.remove(which);
+            expected.remove(&which);
+            assert_eq!(bitmap.max(), expected.last().copied());
+            assert_eq!(bitmap.min(), expected.first().copied());
+        }
+        assert!(!bitmap.contains(0));
+        bitmap.remove(1 << 40);
+    }
+
+    #[test]
+    fn sparse_buckets() {
+        use super::HierarchicalBitmap;
+        use crate::my_priority_queue::AbstractPriorityQueue;
+        use crate::nested_queue::BucketQueue;
+        use crate::no_priority_queue::NoPriorityQueue;
+        use std::collections::HashMap;
+
+        let mut q = BucketQueue::<
+            usize,
+            usize,
+            usize,
+            NoPriorityQueue<usize, usize>,
+            HashMap<usize, NoPriorityQueue<usize, usize>>,
+            HierarchicalBitmap,
+        >::new(0, 0, &NoPriorityQueue::new());
+        for z in [5, 1 << 16, 0, 77, 1 << 12, 5] {
+            q.my_enqueue(z, z);
+        }
+        q.enqueue_batch([], 1 << 35);
+        assert_eq!(q.my_len(), 6);
+        assert_eq!(q.my_peek(), Some((&(1 << 16), &0)));
+        assert_eq!(q.my_dequeue(), Some((1 << 16, 0)));
+        let all_out: Vec<_> = q.dequeue_batch(4, 4).into_iter().map(|(z, _)| z).collect();
+        assert_eq!(all_out, vec![1 << 12, 77, 5, 5]);
+        assert_eq!(q.my_dequeue(), Some((0, 0)));
+        assert!(q.is_empty());
+        assert!(q.my_dequeue().is_none());
+    }
+}
@@ -0,0 +1,374 @@
+use nonempty::NonEmpty;
+use std::hash::Hash;
+
+use crate::friend_level::{partition_friends, split_policies, FriendLevel, LevelPolicy};
+use crate::israeli_priority::{FoyerPreemption, Friendly};
+use crate::my_priority_queue::{AbstractPriorityQueue, GivesBackPriorities};
+
+/// friendship that nests, like organization then team then user
+/// items that share the entire path travel together
+/// and items that only share the beginning of the path are kept adjacent where possible
+pub trait HierarchicallyFriendly<H: Hash + Eq> {
+    /// the shibboleths from the outermost friend group to the innermost one
+    /// all items in the same queue must give paths of the same length
+    fn friendship_path(&self) -> NonEmpty<H>;
+}
+
+/// a single shibboleth is a path of length one
+impl<T: Friendly<H>, H: Hash + Eq> HierarchicallyFriendly<H> for T {
+    fn friendship_path(&self) -> NonEmpty<H> {
+        NonEmpty::singleton(self.friendship_shibboleth())
+    }
+}
+
+/// The `IsraeliPriority` but where friendship is a path of shibboleths.
+/// At every level there is a priority queue of the friend groups at that level
+/// and the group at the head of the line goes in entirely before any other group at that level,
+/// so items sharing the entire path travel together
+/// and groups sharing the beginning of their path stay adjacent.
+/// Each level has its own way of combining priorities and its own ``foyer`` preemption.
+/// With paths of length one, this is the same as `IsraeliPriority`.
+pub struct HierarchicalIsraeliPriority<T, P, H>
+where
+    T: HierarchicallyFriendly<H>,
+    P: Ord + Clone,
+    H: Eq + Hash + Clone,
+{
+    friend_groups: FriendLevel<T, P, H>,
+    /// from the outermost level inwards, one for every level
+    policies: Vec<LevelPolicy<P>>,
+}
+
+impl<T, P, H> HierarchicalIsraeliPriority<T, P, H>
+where
+    T: HierarchicallyFriendly<H>,
+    P: Ord + Clone,
+    H: Eq + Hash + Clone,
+{
+    /// setup for friendship paths of length `depth`
+    /// at every level, the priorities combine by picking the bigger of the two
+    /// and the group in the ``foyer`` always finishes going in
+    /// just like the defaults for `IsraeliPriority`
+    /// # Panics
+    /// if `depth` is 0
+    #[must_use]
+    pub fn with_depth(depth: usize) -> Self {
+        assert!(depth > 0, "friendship paths are nonempty");
+        Self {
+            friend_groups: FriendLevel::with_capacity(8),
+            policies: vec![LevelPolicy::default(); depth],
+        }
+    }
+
+    /// how long the friendship paths are
+    pub fn depth(&self) -> usize {
+        self.policies.len()
+    }
+
+    /// provide a different way for priorities to combine at `level`
+    /// with 0 being the outermost friend groups
+    /// # Panics
+    /// if `level` is not less than the depth
+    pub fn change_combiner(&mut self, level: usize, new_combiner: fn(&P, &P) -> (bool, P)) {
+        self.policies[level].priority_combiner = new_combiner;
+    }
+
+    /// let a sufficiently high priority waiting group at `level`
+    /// cut in front of the group in the ``foyer`` at that level
+    /// # Panics
+    /// if `level` is not less than the depth
+    pub fn change_foyer_preemption(&mut self, level: usize, new_preemption: FoyerPreemption<P>) {
+        self.policies[level].foyer_preemption = new_preemption;
+    }
+
+    /// a group of friends all with the path `the_path` join the queue
+    fn enqueue_friends(
+        &mut self,
+        the_path: &NonEmpty<H>,
+        new_batch: NonEmpty<T>,
+        new_batch_priority: P,
+    ) {
+        assert_eq!(
+            the_path.len(),
+            self.depth(),
+            "all friendship paths in the queue should be the same length"
+        );
+        let (outermost_policy, deeper_policies) = split_policies(&self.policies);
+        self.friend_groups.enqueue_friends(
+            &the_path.head,
+            &the_path.tail,
+            new_batch,
+            new_batch_priority,
+            outermost_policy,
+            deeper_policies,
+        );
+    }
+
+    /// take the entire outermost group with this shibboleth out of the queue
+    /// whether they are waiting in line or in the ``foyer``
+    /// the innermost groups come out whole, the one in the ``foyer`` first
+    /// and then the rest in the order of their priorities
+    pub fn remove_group(&mut self, which: &H) -> Option<(NonEmpty<T>, P)> {
+        self.friend_groups
+            .remove_group(which)
+            .map(|(friend_grp, group_priority)| (friend_grp.into_items(), group_priority))
+    }
+
+    /// overwrite the priority of the entire outermost group with this shibboleth
+    /// without going through the combiner, giving back the old priority
+    pub fn set_group_priority(&mut self, which: &H, new_priority: P) -> Option<P> {
+        self.friend_groups.set_group_priority(which, new_priority)
+    }
+}
+
+/// read only queries about where the outermost friend groups are in line
+/// these are the same as for `IsraeliPriority`
+impl<T, P, H> HierarchicalIsraeliPriority<T, P, H>
+where
+    T: HierarchicallyFriendly<H>,
+    P: Ord + Clone,
+    H: Eq + Hash + Clone,
+{
+    pub fn num_waiting_groups(&self) -> usize {
+        self.friend_groups.num_waiting_groups()
+    }
+
+    pub fn is_in_foyer(&self, which: &H) -> bool {
+        self.friend_groups.is_in_foyer(which)
+    }
+
+    pub fn group_len(&self, which: &H) -> Option<usize> {
+        self.friend_groups.group_len(which)
+    }
+
+    pub fn group_priority(&self, which: &H) -> Option<&P> {
+        self.friend_groups.group_priority(which)
+    }
+
+    pub fn waiting_rank(&self, which: &H) -> Option<usize> {
+        self.friend_groups.waiting_rank(which)
+    }
+
+    pub fn items_ahead(&self, which: &H) -> Option<usize> {
+        self.friend_groups
+            .items_ahead(which, &self.policies[0].foyer_preemption)
+    }
+}
+
+impl<T, P, H> AbstractPriorityQueue<T, P> for HierarchicalIsraeliPriority<T, P, H>
+where
+    T: HierarchicallyFriendly<H>,
+    P: Ord + Clone,
+    H: Hash + Eq + Clone,
+{
+    fn empty_copy(&self) -> Self {
+        Self {
+            friend_groups: FriendLevel::with_capacity(self.friend_groups.num_waiting_groups()),
+            policies: self.policies.clone(),
+        }
+    }
+
+    fn my_peek(&self) -> Option<(&T, &P)> {
+        let (outermost_policy, deeper_policies) = split_policies(&self.policies);
+        self.friend_groups.peek(outermost_policy, deeper_policies)
+    }
+
+    fn my_enqueue(&mut self, new_obj: T, new_obj_priority: P) {
+        let my_path = new_obj.friendship_path();
+        self.enqueue_friends(&my_path, NonEmpty::singleton(new_obj), new_obj_priority);
+    }
+
+    /// items with the same path join as a whole
+    fn enqueue_batch(&mut self, new_batch: impl IntoIterator<Item = T>, new_batch_priority: P) {
+        if let Some(new_batch) = NonEmpty::from_vec(new_batch.into_iter().collect()) {
+            let with_paths = new_batch.map(|z| (z.friendship_path(), z));
+            for (my_path, friend_grp) in partition_friends(with_paths) {
+                self.enqueue_friends(&my_path, friend_grp, new_batch_priority.clone());
+            }
+        }
+    }
+
+    fn my_dequeue(&mut self) -> Option<(T, P)> {
+        let (outermost_policy, deeper_policies) = split_policies(&self.policies);
+        self.friend_groups
+            .dequeue(outermost_policy, deeper_policies)
+    }
+
+    /// keeps going past `around_how_many` if the innermost friend group that is going in
+    /// still has members, but not past `hard_limit`
+    fn dequeue_batch(&mut self, around_how_many: usize, hard_limit: usize) -> Vec<(T, P)> {
+        let mut to_return = Vec::with_capacity(around_how_many);
+        while to_return.len() < hard_limit
+            && (to_return.len() < around_how_many || self.friend_groups.mid_friend_group())
+        {
+            if let Some(real_put_in) = self.my_dequeue() {
+                to_return.push(real_put_in);
+            } else {
+                break;
+            }
+        }
+        to_return
+    }
+
+    fn my_len(&self) -> usize {
+        self.friend_groups.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.friend_groups.is_empty()
+    }
+}
+
+/// the priority of the innermost group they were in
+impl<T, P, H> GivesBackPriorities<T, P> for HierarchicalIsraeliPriority<T, P, H>
+where
+    T: HierarchicallyFriendly<H>,
+    P: Ord + Clone,
+    H: Hash + Eq + Clone,
+{
+}
+
+mod test {
+    use super::HierarchicallyFriendly;
+    use crate::Friendly;
+    use nonempty::NonEmpty;
+
+    const MY_U8_FREINDLINESS: u8 = 5;
+
+    #[allow(dead_code)]
+    #[derive(PartialEq, Eq, Debug)]
+    #[repr(transparent)]
+    struct MyU8(u8);
+    impl Friendly<u8> for MyU8 {
+        fn friendship_shibboleth(&self) -> u8 {
+            self.0 % MY_U8_FREINDLINESS
+        }
+    }
+
+    /// organization is the tens digit and team is the ones digit
+    #[allow(dead_code)]
+    #[derive(PartialEq, Eq, Debug)]
+    #[repr(transparent)]
+    struct OrgTeam(u8);
+    impl HierarchicallyFriendly<u8> for OrgTeam {
+        fn friendship_path(&self) -> NonEmpty<u8> {
+            nonempty::nonempty![self.0 / 10, self.0 % 10]
+        }
+    }
+
+    #[test]
+    fn nothing_in_out() {
+        use super::HierarchicalIsraeliPriority;
+        use crate::my_priority_queue::AbstractPriorityQueue;
+
+        let mut q = HierarchicalIsraeliPriority::<OrgTeam, u8, u8>::with_depth(2);
+        assert_eq!(q.my_len(), 0);
+        assert!(q.is_empty());
+        assert!(q.my_peek().is_none());
+        assert!(q.my_dequeue().is_none());
+        assert!(q.dequeue_batch(10, 10).is_empty());
+    }
+
+    #[test]
+    fn depth_one_is_israeli() {
+        use super::HierarchicalIsraeliPriority;
+        use crate::my_priority_queue::AbstractPriorityQueue;
+        use crate::{FoyerPreemption, IsraeliPriority};
+
+        for preemption in [FoyerPreemption::Never, FoyerPreemption::AtLeast(5)] {
+            let mut q1 = IsraeliPriority::<MyU8, u8, u8>::with_capacity(8);
+            let mut q2 = HierarchicalIsraeliPriority::<MyU8, u8, u8>::with_depth(1);
+            q1.change_foyer_preemption(preemption.clone());
+            q2.change_foyer_preemption(0, preemption);
+            let mut step = 0u8;
+            for round in 0..6u8 {
+                for _ in 0..=round {
+                    step = step.wrapping_mul(37).wrapping_add(11);
+                    q1.my_enqueue(MyU8(step), step % 7);
+                    q2.my_enqueue(MyU8(step), step % 7);
+                }
+                q1.enqueue_batch([MyU8(round), MyU8(round + 1)], round);
+                q2.enqueue_batch([MyU8(round), MyU8(round + 1)], round);
+                for _ in 0..round / 2 + 1 {
+                    assert_eq!(q1.my_dequeue(), q2.my_dequeue());
+                    assert_eq!(q1.my_len(), q2.my_len());
+                }
+            }
+            while !q1.is_empty() {
+                assert_eq!(q2.my_peek().map(|(_, p)| *p), q1.my_peek().map(|(_, p)| *p));
+                assert_eq!(q1.my_dequeue(), q2.my_dequeue());
+            }
+            assert!(q2.is_empty());
+        }
+    }
+
+    #[test]
+    fn teams_travel_together() {
+        use super::HierarchicalIsraeliPriority;
+        use crate::my_priority_queue::AbstractPriorityQueue;
+
+        let mut q = HierarchicalIsraeliPriority::<OrgTeam, u8, u8>::with_depth(2);
+        q.my_enqueue(OrgTeam(11), 1);
+        q.my_enqueue(OrgTeam(23), 2);
+        q.my_enqueue(OrgTeam(12), 5);
+        q.my_enqueue(OrgTeam(21), 4);
+        q.my_enqueue(OrgTeam(11), 0);
+        q.my_enqueue(OrgTeam(12), 0);
+        assert_eq!(q.my_len(), 6);
+        assert_eq!(q.my_peek(), Some((&OrgTeam(12), &5)));
+        // organization 1 was boosted to 5 by team 2 so goes first
+        // and within it, team 2 goes before team 1
+        assert_eq!(q.my_dequeue(), Some((OrgTeam(12), 5)));
+        // team 2 of organization 1 is going in, so another of them joins them
+        q.my_enqueue(OrgTeam(12), 0);
+        // organization 2 can not get ahead of organization 1 which is going in
+        q.my_enqueue(OrgTeam(22), 9);
+        let all_out = q.dequeue_batch(3, 3);
+        assert_eq!(
+            all_out,
+            vec![(OrgTeam(12), 5), (OrgTeam(12), 5), (OrgTeam(11), 1)]
+        );
+        assert_eq!(q.dequeue_batch(1, 4), vec![(OrgTeam(11), 1)]);
+        assert_eq!(
+            q.drain_all(),
+            vec![(OrgTeam(22), 9), (OrgTeam(21), 4), (OrgTeam(23), 2)]
+        );
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn inner_preemption_and_groups() {
+        use super::HierarchicalIsraeliPriority;
+        use crate::my_priority_queue::AbstractPriorityQueue;
+        use crate::FoyerPreemption;
+
+        let mut q = HierarchicalIsraeliPriority::<OrgTeam, u8, u8>::with_depth(2);
+        q.change_foyer_preemption(1, FoyerPreemption::AtLeast(8));
+        q.enqueue_batch([OrgTeam(11), OrgTeam(11)], 2);
+        q.enqueue_batch([OrgTeam(12), OrgTeam(21)], 1);
+        q.enqueue_batch([OrgTeam(22), OrgTeam(22)], 0);
+        q.set_group_priority(&2, 0);
+        assert_eq!(q.num_waiting_groups(), 2);
+        assert_eq!(q.group_len(&1), Some(3));
+        assert_eq!(q.items_ahead(&2), Some(3));
+
+        assert_eq!(q.my_dequeue(), Some((OrgTeam(11), 2)));
+        assert!(q.is_in_foyer(&1));
+        // team 3 is urgent enough to push team 1 aside within organization 1
+        // but organization 2 still waits for all of organization 1
+        q.my_enqueue(OrgTeam(13), 9);
+        assert_eq!(q.group_priority(&1), Some(&9));
+        assert_eq!(q.my_peek(), Some((&OrgTeam(13), &9)));
+        assert_eq!(q.my_dequeue(), Some((OrgTeam(13), 9)));
+        assert_eq!(q.my_dequeue(), Some((OrgTeam(11), 2)));
+
+        let (removed, removed_priority) = q.remove_group(&2).expect("organization 2 is waiting");
+        assert_eq!(removed_priority, 0);
+        assert_eq!(
+            removed.into_iter().collect::<Vec<_>>(),
+            vec![OrgTeam(21), OrgTeam(22), OrgTeam(22)]
+        );
+        assert_eq!(q.drain_all(), vec![(OrgTeam(12), 1)]);
+        assert!(q.is_empty());
+    }
+}
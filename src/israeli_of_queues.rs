@@ -0,0 +1,307 @@
+use nonempty::NonEmpty;
+use std::hash::Hash;
+
+use crate::friend_level::{
+    partition_friends, FriendLevel, GroupOfFriends, LevelPolicy, PriorityCombiner,
+};
+use crate::israeli_priority::{FoyerPreemption, Friendly};
+use crate::my_priority_queue::{AbstractPriorityQueue, GivesBackPriorities};
+
+/// Like the `IsraeliPriority`, friends with the same shibboleth are grouped together
+/// and the groups go in by their combined priority, one group in the ``foyer`` at a time.
+/// But rather than each group being a list of friends going in the order they joined,
+/// each group is its own queue, an empty copy of `group_template`, like a `BucketQueue`.
+/// So inside a group the items go in the order that queue gives,
+/// and they come out with the priority it gives back rather than the priority of the group.
+pub struct IsraeliOfQueues<T, P, H, Q>
+where
+    T: Friendly<H>,
+    P: Ord + Clone,
+    H: Eq + Hash + Clone,
+    Q: AbstractPriorityQueue<T, P>,
+{
+    friend_groups: FriendLevel<T, P, H, QueueGroup<Q>>,
+    group_template: Q,
+    policy: LevelPolicy<P>,
+}
+
+/// a queue as a friend group, there are no levels below it
+#[repr(transparent)]
+struct QueueGroup<Q>(Q);
+
+/// the template each new group is an empty copy of comes along as the context
+impl<T, P, H, Q> GroupOfFriends<T, P, H> for QueueGroup<Q>
+where
+    P: Ord + Clone,
+    Q: AbstractPriorityQueue<T, P>,
+{
+    type Context = Q;
+
+    fn new_group(
+        _rest_of_path: &[H],
+        new_batch: NonEmpty<T>,
+        new_batch_priority: &P,
+        group_template: &Q,
+    ) -> Self {
+        let mut new_group = group_template.empty_copy();
+        new_group.enqueue_batch(new_batch, new_batch_priority.clone());
+        Self(new_group)
+    }
+
+    fn enqueue(
+        &mut self,
+        _rest_of_path: &[H],
+        new_batch: NonEmpty<T>,
+        new_batch_priority: P,
+        _group_template: &Q,
+    ) {
+        self.0.enqueue_batch(new_batch, new_batch_priority);
+    }
+
+    fn len(&self) -> usize {
+        self.0.my_len()
+    }
+
+    /// the priority the queue gives back rather than `group_priority`
+    fn peek<'a>(&'a self, _group_priority: &'a P, _group_template: &Q) -> Option<(&'a T, &'a P)> {
+        self.0.my_peek()
+    }
+
+    fn dequeue(mut self, _group_priority: &P, _group_template: &Q) -> ((T, P), Option<Self>) {
+        let ret_val = self
+            .0
+            .my_dequeue()
+            .expect("empty groups are never kept around");
+        if self.0.is_empty() {
+            (ret_val, None)
+        } else {
+            (ret_val, Some(self))
+        }
+    }
+
+    fn mid_friend_group(&self) -> bool {
+        true
+    }
+}
+
+impl<T, P, H, Q> IsraeliOfQueues<T, P, H, Q>
+where
+    T: Friendly<H>,
+    P: Ord + Clone,
+    H: Eq + Hash + Clone,
+    Q: AbstractPriorityQueue<T, P>,
+{
+    /// setup with capacity for specified number of distinct friend groups
+    /// each of which is an empty copy of `group_template`
+    /// the priorities of a group combine by picking the bigger, as in `IsraeliPriority`
+    pub fn with_capacity(capacity: usize, group_template: &Q) -> Self {
+        Self {
+            friend_groups: FriendLevel::with_capacity(capacity),
+            group_template: group_template.empty_copy(),
+            policy: LevelPolicy::default(),
+        }
+    }
+
+    /// see `IsraeliPriority::change_combiner`
+    pub fn change_combiner(&mut self, new_combiner: PriorityCombiner<P>) {
+        self.policy.priority_combiner = new_combiner;
+    }
+
+    /// see `IsraeliPriority::change_foyer_preemption`
+    pub fn change_foyer_preemption(&mut self, new_preemption: FoyerPreemption<P>) {
+        self.policy.foyer_preemption = new_preemption;
+    }
+
+    /// a group of friends all with `the_shibboleth` join the queue
+    /// either with their friends waiting in line, with their friends in the ``foyer``
+    /// or as a new group at the back of the line
+    fn enqueue_friends(
+        &mut self,
+        the_shibboleth: &H,
+        new_batch: NonEmpty<T>,
+        new_batch_priority: P,
+    ) {
+        self.friend_groups.enqueue_friends(
+            the_shibboleth,
+            &[],
+            new_batch,
+            new_batch_priority,
+            &self.policy,
+            &self.group_template,
+        );
+    }
+
+    /// take the entire group with this shibboleth out of the queue
+    /// whether they are waiting in line or in the ``foyer``
+    pub fn remove_group(&mut self, which: &H) -> Option<(Q, P)> {
+        self.friend_groups
+            .remove_group(which)
+            .map(|(QueueGroup(friend_grp), group_priority)| (friend_grp, group_priority))
+    }
+
+    /// is the group with this shibboleth the one currently entering
+    pub fn is_in_foyer(&self, which: &H) -> bool {
+        self.friend_groups.is_in_foyer(which)
+    }
+
+    /// the current combined priority of the group with this shibboleth
+    /// whether they are waiting or in the ``foyer``
+    pub fn group_priority(&self, which: &H) -> Option<&P> {
+        self.friend_groups.group_priority(which)
+    }
+
+    /// the queue of the group with this shibboleth
+    /// whether they are waiting or in the ``foyer``
+    pub fn group(&self, which: &H) -> Option<&Q> {
+        self.friend_groups
+            .group(which)
+            .map(|QueueGroup(friend_grp)| friend_grp)
+    }
+}
+
+impl<T, P, H, Q> AbstractPriorityQueue<T, P> for IsraeliOfQueues<T, P, H, Q>
+where
+    T: Friendly<H>,
+    P: Ord + Clone,
+    H: Eq + Hash + Clone,
+    Q: AbstractPriorityQueue<T, P>,
+{
+    // newer compilers warn that this has no effect on a trait impl
+    #[allow(unused_attributes)]
+    #[must_use]
+    fn empty_copy(&self) -> Self {
+        let mut to_return = Self::with_capacity(
+            self.friend_groups.num_waiting_groups(),
+            &self.group_template,
+        );
+        to_return.policy = self.policy.clone();
+        to_return
+    }
+
+    fn my_peek(&self) -> Option<(&T, &P)> {
+        self.friend_groups.peek(&self.policy, &self.group_template)
+    }
+
+    fn my_enqueue(&mut self, new_obj: T, new_obj_priority: P) {
+        let my_shibboleth = new_obj.friendship_shibboleth();
+        self.enqueue_friends(
+            &my_shibboleth,
+            NonEmpty::singleton(new_obj),
+            new_obj_priority,
+        );
+    }
+
+    fn enqueue_batch(&mut self, new_batch: impl IntoIterator<Item = T>, new_batch_priority: P) {
+        if let Some(new_batch) = NonEmpty::from_vec(new_batch.into_iter().collect()) {
+            let with_shibboleths = new_batch.map(|z| (z.friendship_shibboleth(), z));
+            for (my_shibboleth, friend_grp) in partition_friends(with_shibboleths) {
+                self.enqueue_friends(&my_shibboleth, friend_grp, new_batch_priority.clone());
+            }
+        }
+    }
+
+    fn my_dequeue(&mut self) -> Option<(T, P)> {
+        self.friend_groups
+            .dequeue(&self.policy, &self.group_template)
+    }
+
+    fn dequeue_batch(&mut self, around_how_many: usize, hard_limit: usize) -> Vec<(T, P)> {
+        assert!(hard_limit >= around_how_many);
+        let mut to_return = Vec::with_capacity(around_how_many);
+        for _ in 0..around_how_many {
+            let put_in = self.my_dequeue();
+            if let Some(real_put_in) = put_in {
+                to_return.push(real_put_in);
+            } else {
+                break;
+            }
+        }
+        to_return
+    }
+
+    fn my_len(&self) -> usize {
+        self.friend_groups.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.friend_groups.is_empty()
+    }
+}
+
+/// whatever priority the group's queue gives back
+impl<T, P, H, Q> GivesBackPriorities<T, P> for IsraeliOfQueues<T, P, H, Q>
+where
+    T: Friendly<H>,
+    P: Ord + Clone,
+    H: Eq + Hash + Clone,
+    Q: GivesBackPriorities<T, P>,
+{
+}
+
+mod test {
+    use crate::Friendly;
+
+    const MY_U8_FREINDLINESS: u8 = 5;
+
+    #[allow(dead_code)]
+    #[derive(PartialEq, Eq, Debug, Hash)]
+    #[repr(transparent)]
+    struct MyU8(u8);
+    impl Friendly<u8> for MyU8 {
+        fn friendship_shibboleth(&self) -> u8 {
+            self.0 % MY_U8_FREINDLINESS
+        }
+    }
+
+    #[test]
+    fn groups_are_buckets() {
+        use super::IsraeliOfQueues;
+        use crate::coarse_grainers::DivideBy;
+        use crate::my_priority_queue::AbstractPriorityQueue;
+        use crate::{BucketQueueBuilder, NoPriorityQueue};
+
+        let mut q = IsraeliOfQueues::<MyU8, u8, u8, _>::with_capacity(
+            4,
+            &BucketQueueBuilder::<MyU8, u8, DivideBy<u8, 10>, _>::new(NoPriorityQueue::new())
+                .build(),
+        );
+        assert!(q.is_empty());
+        // group 1 gets in line first and group 3 joins the line after at a higher priority
+        q.enqueue_batch([MyU8(1), MyU8(6)], 5);
+        q.my_enqueue(MyU8(3), 30);
+        q.my_enqueue(MyU8(11), 35);
+        assert_eq!(q.my_len(), 4);
+        assert_eq!(q.group_priority(&1), Some(&35));
+        // group 1 is boosted past group 3 and inside it the buckets decide the order
+        assert_eq!(q.my_peek().map(|(z, _)| z), Some(&MyU8(11)));
+        assert_eq!(q.my_dequeue().map(|(z, _)| z), Some(MyU8(11)));
+        assert!(q.is_in_foyer(&1));
+        // joining the group in the ``foyer`` goes into their buckets too
+        q.my_enqueue(MyU8(16), 12);
+        assert_eq!(q.group(&1).map(AbstractPriorityQueue::my_len), Some(3));
+        let all_out: Vec<_> = q.drain_all().into_iter().map(|(z, _)| z.0).collect();
+        assert_eq!(all_out, vec![16, 1, 6, 3]);
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn foyer_preemption() {
+        use super::IsraeliOfQueues;
+        use crate::my_priority_queue::AbstractPriorityQueue;
+        use crate::FoyerPreemption;
+        use priority_queue::PriorityQueue;
+
+        let mut q = IsraeliOfQueues::<MyU8, u8, u8, _>::with_capacity(4, &PriorityQueue::new());
+        q.change_foyer_preemption(FoyerPreemption::AtLeast(15));
+        q.enqueue_batch([MyU8(0), MyU8(5)], 1);
+        q.my_enqueue(MyU8(10), 2);
+        assert_eq!(q.my_dequeue(), Some((MyU8(10), 2)));
+        q.my_enqueue(MyU8(1), 20);
+        assert_eq!(q.my_peek(), Some((&MyU8(1), &20)));
+        assert_eq!(q.my_dequeue(), Some((MyU8(1), 20)));
+        let (group_zero, group_priority) = q.remove_group(&0).expect("waiting again");
+        assert_eq!(group_priority, 2);
+        assert_eq!(group_zero.my_len(), 2);
+        assert!(q.is_empty());
+    }
+}
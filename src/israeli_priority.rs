@@ -1,8 +1,8 @@
 use nonempty::NonEmpty;
-use priority_queue::PriorityQueue;
-use std::{cmp::Ordering, collections::HashMap, hash::Hash};
+use std::{hash::Hash, time::Duration};
 
-use crate::my_priority_queue::AbstractPriorityQueue;
+use crate::friend_level::{partition_friends, FriendGroup, FriendLevel, LevelPolicy};
+use crate::my_priority_queue::{AbstractPriorityQueue, GivesBackPriorities};
 
 /// get a shibooleth that we can compare with equality
 /// to judge if two items in the queue are friends or not
@@ -16,14 +16,73 @@ pub trait Friendly<H: Hash + Eq> {
     fn friendship_shibboleth(&self) -> H;
 }
 
-/// are all items in that nonempty collection friends of each other
-fn are_all_friends<T: Friendly<H>, H: Hash + Eq>(new_batch: &NonEmpty<T>) -> (bool, H) {
-    let the_shibboleth = new_batch.head.friendship_shibboleth();
-    let all_friends = new_batch
-        .tail
-        .iter()
-        .all(|r| r.friendship_shibboleth() == the_shibboleth);
-    (all_friends, the_shibboleth)
+/// when may a group waiting in line push aside the group
+/// that is currently in the ``foyer``
+/// the rest of the ``foyer`` group goes back into the line
+/// with their priority and shibboleth restored
+#[derive(Clone)]
+pub enum FoyerPreemption<P> {
+    /// the group that is currently entering always finishes entering
+    Never,
+    /// a waiting group with priority at least this may cut in front of the ``foyer``
+    AtLeast(P),
+    /// a waiting group may cut in front of the group in the ``foyer``
+    /// when its priority is above the ``foyer`` priority by more than `margin`
+    /// `difference` gives how far the first priority is above the second
+    /// or None when that is too big for `P`, which is more than any margin
+    ByMargin {
+        margin: P,
+        difference: fn(&P, &P) -> Option<P>,
+    },
+}
+
+/// how far one priority is above a smaller one
+/// None when that does not fit, instead of overflowing
+pub trait CheckedDifference: Sized {
+    fn checked_difference(&self, smaller: &Self) -> Option<Self>;
+}
+
+macro_rules! checked_difference {
+    ($($t:ty),*) => {
+        $(
+            impl CheckedDifference for $t {
+                fn checked_difference(&self, smaller: &Self) -> Option<Self> {
+                    self.checked_sub(*smaller)
+                }
+            }
+        )*
+    };
+}
+
+checked_difference!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, Duration);
+
+impl<P: Ord> FoyerPreemption<P> {
+    /// does a waiting group with `waiting_priority` push aside
+    /// the group in the ``foyer`` which has `foyer_priority`
+    /// regardless of the policy, the waiting group must be strictly higher priority
+    /// otherwise the two groups could keep pushing each other aside
+    pub fn preempts(&self, waiting_priority: &P, foyer_priority: &P) -> bool {
+        if waiting_priority <= foyer_priority {
+            return false;
+        }
+        match self {
+            Self::Never => false,
+            Self::AtLeast(threshold) => waiting_priority >= threshold,
+            Self::ByMargin { margin, difference } => {
+                difference(waiting_priority, foyer_priority).is_none_or(|d| d > *margin)
+            }
+        }
+    }
+}
+
+impl<P: CheckedDifference> FoyerPreemption<P> {
+    /// preempt when the waiting priority exceeds the ``foyer`` priority by more than `margin`
+    pub fn by_margin(margin: P) -> Self {
+        Self::ByMargin {
+            margin,
+            difference: P::checked_difference,
+        }
+    }
 }
 
 /// This isn't really an Israeli queue,
@@ -31,17 +90,17 @@ fn are_all_friends<T: Friendly<H>, H: Hash + Eq>(new_batch: &NonEmpty<T>) -> (bo
 /// But by using a trait with a generic we can avoid that iteration.
 /// That way we have the regular priority queue for the shibboleths
 /// and how the shibboleths translate to nonempty lists of items.
+/// Every item has `friendship_shibboleth` called exactly once when it is enqueued,
+/// after that each group only keeps track of the one shibboleth they all share.
+/// This is the `HierarchicalIsraeliPriority` with paths of length one.
 pub struct IsraeliPriority<T, P, H>
 where
     T: Friendly<H>,
     P: Ord + Clone,
     H: Eq + Hash + Clone,
 {
-    underlying: PriorityQueue<H, P>,
-    current_friend_group: Option<(NonEmpty<T>, P)>,
-    shibboleth_to_friends: HashMap<H, NonEmpty<T>>,
-    waiting_len: usize,
-    priority_combiner: fn(&P, &P) -> (bool, P),
+    friend_groups: FriendLevel<T, P, H>,
+    policy: LevelPolicy<P>,
 }
 
 impl<T, P, H> Default for IsraeliPriority<T, P, H>
@@ -68,19 +127,9 @@ where
     /// their priority and move that entire group up even further
     #[must_use]
     pub fn with_capacity(capacity: usize) -> Self {
-        let default_combiner = |p1: &P, p2: &P| {
-            if p1.cmp(p2) == Ordering::Less {
-                (true, p2.clone())
-            } else {
-                (false, p1.clone())
-            }
-        };
         Self {
-            underlying: PriorityQueue::with_capacity(capacity),
-            current_friend_group: None,
-            shibboleth_to_friends: HashMap::with_capacity(capacity >> 3),
-            waiting_len: 0,
-            priority_combiner: default_combiner,
+            friend_groups: FriendLevel::with_capacity(capacity),
+            policy: LevelPolicy::default(),
         }
     }
 
@@ -88,260 +137,233 @@ where
     /// when a portion is already in line and a new friend joins
     /// that friend group
     pub fn change_combiner(&mut self, new_combiner: fn(&P, &P) -> (bool, P)) {
-        self.priority_combiner = new_combiner;
+        self.policy.priority_combiner = new_combiner;
+    }
+
+    /// by default the group in the ``foyer`` always finishes going in
+    /// but this lets a sufficiently high priority waiting group cut in front of them
+    pub fn change_foyer_preemption(&mut self, new_preemption: FoyerPreemption<P>) {
+        self.policy.foyer_preemption = new_preemption;
+    }
+
+    /// a group of friends all with `the_shibboleth` join the queue
+    /// either with their friends waiting in line, with their friends in the ``foyer``
+    /// or as a new group at the back of the line
+    fn enqueue_friends(
+        &mut self,
+        the_shibboleth: &H,
+        new_batch: NonEmpty<T>,
+        new_batch_priority: P,
+    ) {
+        self.friend_groups.enqueue_friends(
+            the_shibboleth,
+            &[],
+            new_batch,
+            new_batch_priority,
+            &self.policy,
+            &[],
+        );
+    }
+
+    /// take the entire group with this shibboleth out of the queue
+    /// whether they are waiting in line or in the ``foyer``
+    pub fn remove_group(&mut self, which: &H) -> Option<(NonEmpty<T>, P)> {
+        self.friend_groups
+            .remove_group(which)
+            .map(|(friend_grp, group_priority)| (friend_grp.into_items(), group_priority))
+    }
+
+    /// overwrite the priority of the entire group with this shibboleth
+    /// without going through the combiner, giving back the old priority
+    /// a group in the ``foyer`` stays there even if this lowers their priority
+    pub fn set_group_priority(&mut self, which: &H, new_priority: P) -> Option<P> {
+        self.friend_groups.set_group_priority(which, new_priority)
     }
 
     /// either there is a batch of friends currently in the process of going in
     /// get them all and their shared priority
-    /// otherwise dequeue only one element and that can bring their friends
-    /// as well through the functionality of `my_dequeue`
-    fn israeli_dequeue_batch(&mut self) -> Option<(NonEmpty<T>, P)> {
-        if let Some(current_batch) = self.current_friend_group.take() {
-            Some((current_batch.0, current_batch.1))
+    /// otherwise take the next group in line, starting with who `my_dequeue` would give
+    /// either way, they all share the shibboleth that is also given
+    fn israeli_dequeue_batch(&mut self) -> Option<(H, NonEmpty<T>, P)> {
+        self.friend_groups
+            .apply_foyer_preemption(&self.policy.foyer_preemption);
+        if let Some((shibboleth, current_batch, priority)) = self.friend_groups.take_foyer_group() {
+            Some((shibboleth, current_batch.into_items(), priority))
         } else {
-            let first_in_next_batch = self.my_dequeue();
-            if let Some((rest_of_batch, priority)) = self.current_friend_group.take() {
-                let real_first =
-                    first_in_next_batch.expect("If there was a rest, then there would be a first");
-                let mut ret_val = NonEmpty::singleton(real_first.0);
+            let (shibboleth, head_of_line, priority) = self.friend_groups.pop_waiting_group()?;
+            let mut rest_of_batch = head_of_line.into_items();
+            // the same order that repeatedly calling `my_dequeue` would start with
+            let ret_val = if let Some(real_first) = rest_of_batch.pop() {
+                let mut ret_val = NonEmpty::singleton(real_first);
                 ret_val.extend(rest_of_batch);
-                Some((ret_val, priority))
+                ret_val
             } else {
-                first_in_next_batch.map(|(z, w)| (NonEmpty::singleton(z), w))
-            }
+                rest_of_batch
+            };
+            Some((shibboleth, ret_val, priority))
         }
     }
 }
 
+/// read only queries about where friend groups are in line
+/// these never change the queue
+impl<T, P, H> IsraeliPriority<T, P, H>
+where
+    T: Friendly<H>,
+    P: Ord + Clone,
+    H: Eq + Hash + Clone,
+{
+    /// how many distinct friend groups are waiting in line
+    /// not counting the one in the ``foyer``
+    pub fn num_waiting_groups(&self) -> usize {
+        self.friend_groups.num_waiting_groups()
+    }
+
+    /// is the group with this shibboleth the one currently entering
+    pub fn is_in_foyer(&self, which: &H) -> bool {
+        self.friend_groups.is_in_foyer(which)
+    }
+
+    /// how many items are in the group with this shibboleth
+    /// whether they are waiting or in the ``foyer``
+    pub fn group_len(&self, which: &H) -> Option<usize> {
+        self.friend_groups.group_len(which)
+    }
+
+    /// the current combined priority of the group with this shibboleth
+    /// whether they are waiting or in the ``foyer``
+    pub fn group_priority(&self, which: &H) -> Option<&P> {
+        self.friend_groups.group_priority(which)
+    }
+
+    /// how many waiting groups have strictly higher priority than the one with this shibboleth
+    /// so 0 means they are at the head of the waiting line
+    /// groups with the same priority are not counted, so this is a lower bound
+    /// a group in the ``foyer`` is not waiting so gives None
+    pub fn waiting_rank(&self, which: &H) -> Option<usize> {
+        self.friend_groups.waiting_rank(which)
+    }
+
+    /// an estimate of how many items will be dequeued before any of the group with this shibboleth
+    /// the remainder of the ``foyer`` group counts unless this group would preempt them
+    /// but groups with the same priority and any future boosts are not accounted for
+    pub fn items_ahead(&self, which: &H) -> Option<usize> {
+        self.friend_groups
+            .items_ahead(which, &self.policy.foyer_preemption)
+    }
+}
+
 impl<T, P, H> AbstractPriorityQueue<T, P> for IsraeliPriority<T, P, H>
 where
     T: Friendly<H>,
     P: Ord + Clone,
     H: Hash + Eq + Clone,
 {
+    // newer compilers warn that this has no effect on a trait impl
+    #[allow(unused_attributes)]
     #[must_use]
     fn empty_copy(&self) -> Self {
-        let mut to_return = Self::with_capacity(self.my_len());
-        to_return.change_combiner(self.priority_combiner);
-        to_return
+        Self {
+            friend_groups: FriendLevel::with_capacity(self.my_len()),
+            policy: self.policy.clone(),
+        }
     }
 
     fn my_peek(&self) -> Option<(&T, &P)> {
-        match &self.current_friend_group {
-            None => self.underlying.peek().map(|(gp, p)| {
-                let z = &self
-                    .shibboleth_to_friends
-                    .get(gp)
-                    .expect("all shibboleths in priority queue have corresponding items")
-                    .head;
-                (z, p)
-            }),
-            Some((real_group, top_priority)) => Some((&real_group.head, top_priority)),
-        }
+        self.friend_groups.peek(&self.policy, &[])
     }
 
     fn my_enqueue(&mut self, new_obj: T, new_obj_priority: P) {
         let my_shibboleth = new_obj.friendship_shibboleth();
-        if let Some(old_priority) = self.underlying.get_priority(&my_shibboleth) {
-            // found friends waiting in line
-            let (priority_changed, new_priority) =
-                (self.priority_combiner)(old_priority, &new_obj_priority);
-            if priority_changed {
-                // the priority can make this group the head of the waiting
-                // but it can't push them up to be past the group that is currently entering
-                // they are already in the ``foyer``
-                let _overwritten = self
-                    .underlying
-                    .change_priority(&my_shibboleth, new_priority);
-            }
-            if let Some(friend_grp) = self.shibboleth_to_friends.get_mut(&my_shibboleth) {
-                friend_grp.push(new_obj);
-            } else {
-                panic!("found shibboleth in priority queue but not the corresponding friends");
-            }
-            self.waiting_len += 1;
-        } else if let Some(head_group) = &mut self.current_friend_group {
-            // there is a head group entering right now, they might be friends
-            if head_group.0.head.friendship_shibboleth() == my_shibboleth {
-                // they are your friends and they are the ones just going in now
-                head_group.0.push(new_obj);
-                let (priority_changed, new_priority) =
-                    (self.priority_combiner)(&head_group.1, &new_obj_priority);
-                if priority_changed {
-                    head_group.1 = new_priority;
-                }
-            } else {
-                // they are not your friends, go into the line
-                // even if you have higher priority than them
-                self.underlying
-                    .push(my_shibboleth.clone(), new_obj_priority);
-                self.shibboleth_to_friends
-                    .insert(my_shibboleth, nonempty::nonempty![new_obj]);
-                self.waiting_len += 1;
-            }
-        } else {
-            // there is no group currently being let in, and you have no friends in line
-            self.underlying
-                .push(my_shibboleth.clone(), new_obj_priority);
-            self.shibboleth_to_friends
-                .insert(my_shibboleth, nonempty::nonempty![new_obj]);
-            self.waiting_len += 1;
-        }
+        self.enqueue_friends(
+            &my_shibboleth,
+            NonEmpty::singleton(new_obj),
+            new_obj_priority,
+        );
     }
 
     fn enqueue_batch(&mut self, new_batch: impl IntoIterator<Item = T>, new_batch_priority: P) {
         if let Some(new_batch) = NonEmpty::from_vec(new_batch.into_iter().collect()) {
-            let (all_friends, the_shibboleth) = are_all_friends(&new_batch);
-            if !all_friends {
-                for z in new_batch {
-                    self.my_enqueue(z, new_batch_priority.clone());
-                }
-                return;
-            }
-            let new_len = new_batch.len();
-            if let Some(old_priority) = self.underlying.get_priority(&the_shibboleth) {
-                // found more friends waiting in line
-                let (priority_changed, new_priority) =
-                    (self.priority_combiner)(old_priority, &new_batch_priority);
-                if priority_changed {
-                    // the priority can make this group the head of the waiting
-                    // but it can't push them up to be past the group that is currently entering
-                    // they are already in the ``foyer``
-                    let _overwritten = self
-                        .underlying
-                        .change_priority(&the_shibboleth, new_priority);
-                }
-                if let Some(friend_grp) = self.shibboleth_to_friends.get_mut(&the_shibboleth) {
-                    friend_grp.extend(new_batch);
-                } else {
-                    panic!("found shibboleth in priority queue but not the corresponding friends");
-                }
-                self.waiting_len += new_len;
-            } else if let Some(head_group) = &mut self.current_friend_group {
-                // there is a head group entering right now, they might be friends
-                if head_group.0.head.friendship_shibboleth() == the_shibboleth {
-                    // they are your friends and they are the ones just going in now
-                    head_group.0.extend(new_batch);
-                    let (priority_changed, new_priority) =
-                        (self.priority_combiner)(&head_group.1, &new_batch_priority);
-                    if priority_changed {
-                        head_group.1 = new_priority;
-                    }
-                } else {
-                    // they are not your friends, go into the line
-                    // even if you have higher priority than them
-                    self.underlying
-                        .push(the_shibboleth.clone(), new_batch_priority);
-                    self.shibboleth_to_friends.insert(the_shibboleth, new_batch);
-                    self.waiting_len += new_len;
-                }
-            } else {
-                // there is no group currently being let in, and you have no friends in line
-                self.underlying
-                    .push(the_shibboleth.clone(), new_batch_priority);
-                self.shibboleth_to_friends.insert(the_shibboleth, new_batch);
-                self.waiting_len += new_len;
+            // split them up into friend groups in the order they first appear
+            // then each of those friend groups joins as a whole
+            let with_shibboleths = new_batch.map(|z| (z.friendship_shibboleth(), z));
+            for (my_shibboleth, friend_grp) in partition_friends(with_shibboleths) {
+                self.enqueue_friends(&my_shibboleth, friend_grp, new_batch_priority.clone());
             }
         }
     }
 
     fn my_dequeue(&mut self) -> Option<(T, P)> {
-        let taken_current_friend_group = self.current_friend_group.take();
-        if let Some((mut head_group, head_priority)) = taken_current_friend_group {
-            if head_group.len() == 1 {
-                Some((head_group.head, head_priority))
-            } else {
-                let ret_val = head_group.pop().map(|z| (z, head_priority.clone()));
-                self.current_friend_group = Some((head_group, head_priority));
-                ret_val
-            }
-        } else {
-            let new_head_group_shibboleth = self.underlying.pop();
-            if let Some((shibboleth, priority)) = new_head_group_shibboleth {
-                // the next batch becomes the currently processing group
-                let head_of_line = self.shibboleth_to_friends.remove(&shibboleth);
-                if let Some(new_head_of_line) = head_of_line {
-                    self.waiting_len -= new_head_of_line.len();
-                    self.current_friend_group = Some((new_head_of_line, priority));
-                    self.my_dequeue()
-                } else {
-                    panic!("found shibboleth in priority queue but not the corresponding friends");
-                }
-            } else {
-                // the entire line is empty
-                None
-            }
-        }
+        self.friend_groups.dequeue(&self.policy, &[])
     }
 
     fn my_len(&self) -> usize {
-        let at_head_len = self.current_friend_group.as_ref().map_or(0, |z| z.0.len());
-        at_head_len + self.waiting_len
+        self.friend_groups.len()
     }
 
     fn is_empty(&self) -> bool {
-        self.waiting_len == 0
-            && self
-                .current_friend_group
-                .as_ref()
-                .is_none_or(|z| z.0.is_empty())
+        self.friend_groups.is_empty()
     }
 
     fn dequeue_batch(&mut self, around_how_many: usize, hard_limit: usize) -> Vec<(T, P)> {
         let mut to_return = Vec::with_capacity(around_how_many);
-        for _ in 0..around_how_many {
-            let put_in = self.israeli_dequeue_batch();
-            if let Some(real_put_in) = put_in {
+        // where each friend group starts in `to_return` and their shibboleth
+        let mut group_starts = Vec::new();
+        while to_return.len() < around_how_many {
+            if let Some((the_shibboleth, real_put_in, put_in_priority)) =
+                self.israeli_dequeue_batch()
+            {
+                group_starts.push((to_return.len(), the_shibboleth));
                 to_return.extend(
                     real_put_in
-                        .0
                         .into_iter()
-                        .map(|z| (z, real_put_in.1.clone())),
+                        .map(|z| (z, put_in_priority.clone())),
                 );
             } else {
                 break;
             }
-            if to_return.len() >= around_how_many {
-                break;
-            }
         }
         if to_return.len() > hard_limit {
-            let left_back = to_return.split_off(hard_limit);
-            if let Some(nonempty_left_back) = NonEmpty::from_vec(left_back) {
-                let all_same_priority = nonempty_left_back
-                    .iter()
-                    .all(|(_, z)| *z == nonempty_left_back.head.1);
-                if all_same_priority {
-                    let (new_batch_head, new_batch_priority) = nonempty_left_back.head;
-                    let the_shibboleth = new_batch_head.friendship_shibboleth();
-                    let mut new_batch = NonEmpty::new(new_batch_head);
-                    new_batch.extend(nonempty_left_back.tail.into_iter().map(|z| z.0));
-                    let all_friends = new_batch
-                        .tail
-                        .iter()
-                        .all(|r| r.friendship_shibboleth() == the_shibboleth);
-                    if self.current_friend_group.is_none() || all_friends {
-                        // the last friend group was too big, some of them go back to the head of the line
-                        self.current_friend_group = Some((new_batch, new_batch_priority));
+            // the groups that were cut off go back to where they were
+            // the earliest one goes back to the ``foyer``
+            // and any after that are back in line
+            for (group_start, the_shibboleth) in group_starts.into_iter().rev() {
+                let left_back = to_return.split_off(std::cmp::max(group_start, hard_limit));
+                if let Some(nonempty_left_back) = NonEmpty::from_vec(left_back) {
+                    let left_back_priority = nonempty_left_back.head.1.clone();
+                    let left_back_group = FriendGroup::Friends(nonempty_left_back.map(|(z, _)| z));
+                    if group_start <= hard_limit {
+                        self.friend_groups.put_in_foyer(
+                            the_shibboleth,
+                            left_back_group,
+                            left_back_priority,
+                        );
                     } else {
-                        // there is a group in the ``foyer``, enqueue them as normal
-                        // or the extras were not all part of 1 friend group
-                        // this should not occur
-                        self.enqueue_batch(new_batch, new_batch_priority);
-                    }
-                } else {
-                    // the extras were not all a part of one group for some reason
-                    // this should not occur
-                    for (a, b) in nonempty_left_back {
-                        self.my_enqueue(a, b);
+                        self.friend_groups.put_back_in_line(
+                            the_shibboleth,
+                            left_back_group,
+                            left_back_priority,
+                        );
                     }
                 }
+                if group_start <= hard_limit {
+                    break;
+                }
             }
         }
         to_return
     }
 }
 
+/// the priority of the group they were in
+impl<T, P, H> GivesBackPriorities<T, P> for IsraeliPriority<T, P, H>
+where
+    T: Friendly<H>,
+    P: Ord + Clone,
+    H: Hash + Eq + Clone,
+{
+}
+
 mod test {
     // TODO nontrivial tests
 
@@ -404,10 +426,11 @@ mod test {
             assert_eq!(q.my_len(), max_num.into());
             assert!(!q.is_empty() || max_num == 0);
             for idx in 0..max_num {
-                assert!(q.current_friend_group.is_none());
+                assert!(!q.is_in_foyer(&idx));
                 let removed = q.my_dequeue();
                 assert_eq!(removed, Some((MyU8(idx), max_num - 1 - idx)));
-                assert!(q.current_friend_group.is_none());
+                assert!(!q.is_in_foyer(&idx));
+                assert_eq!(q.group_len(&idx), None);
             }
             assert!(q.is_empty());
             let removed = q.my_dequeue();
@@ -415,4 +438,211 @@ mod test {
             assert!(q.is_empty());
         }
     }
+
+    #[test]
+    fn foyer_preemption() {
+        use super::{FoyerPreemption, IsraeliPriority};
+        use crate::my_priority_queue::AbstractPriorityQueue;
+
+        let fill = |q: &mut IsraeliPriority<MyU8, u8, u8>| {
+            q.enqueue_batch([MyU8(0), MyU8(5), MyU8(10)], 1);
+            assert_eq!(q.my_dequeue(), Some((MyU8(10), 1)));
+            q.my_enqueue(MyU8(1), 20);
+        };
+
+        let mut q = IsraeliPriority::<MyU8, u8, u8>::with_capacity(4);
+        fill(&mut q);
+        assert_eq!(q.my_peek(), Some((&MyU8(5), &1)));
+        assert_eq!(q.my_dequeue(), Some((MyU8(5), 1)));
+        assert_eq!(q.my_peek(), Some((&MyU8(0), &1)));
+        assert_eq!(q.my_dequeue(), Some((MyU8(0), 1)));
+
+        for policy in [FoyerPreemption::AtLeast(15), FoyerPreemption::by_margin(10)] {
+            let mut q = IsraeliPriority::<MyU8, u8, u8>::with_capacity(4);
+            q.change_foyer_preemption(policy);
+            fill(&mut q);
+            assert_eq!(q.my_peek(), Some((&MyU8(1), &20)));
+            assert_eq!(q.my_dequeue(), Some((MyU8(1), 20)));
+            assert_eq!(q.my_len(), 2);
+            assert!(!q.is_in_foyer(&0));
+            assert_eq!(q.waiting_rank(&0), Some(0));
+            assert_eq!(q.group_priority(&0), Some(&1));
+            assert_eq!(q.drain_all(), vec![(MyU8(5), 1), (MyU8(0), 1)]);
+        }
+
+        for policy in [FoyerPreemption::AtLeast(21), FoyerPreemption::by_margin(19)] {
+            let mut q = IsraeliPriority::<MyU8, u8, u8>::with_capacity(4);
+            q.change_foyer_preemption(policy);
+            fill(&mut q);
+            assert_eq!(q.my_dequeue(), Some((MyU8(5), 1)));
+        }
+
+        // no overflow near the top of the range
+        let policy = FoyerPreemption::by_margin(10u8);
+        assert!(!policy.preempts(&255, &250));
+        assert!(policy.preempts(&255, &244));
+        assert!(!policy.preempts(&250, &255));
+        let policy = FoyerPreemption::by_margin(50i8);
+        assert!(policy.preempts(&100, &-100));
+        assert!(!policy.preempts(&-80, &-100));
+    }
+
+    #[test]
+    fn peek_then_dequeue() {
+        use super::IsraeliPriority;
+        use crate::my_priority_queue::AbstractPriorityQueue;
+
+        let mut q = IsraeliPriority::<MyU8, u8, u8>::with_capacity(4);
+        q.enqueue_batch([MyU8(0), MyU8(5), MyU8(10)], 4);
+        q.my_enqueue(MyU8(1), 2);
+        q.my_enqueue(MyU8(15), 1);
+        q.my_enqueue(MyU8(6), 3);
+        while let Some((peeked, peeked_priority)) = q.my_peek().map(|(z, w)| (z.0, *w)) {
+            assert_eq!(q.my_dequeue(), Some((MyU8(peeked), peeked_priority)));
+        }
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn group_queries() {
+        use super::IsraeliPriority;
+        use crate::my_priority_queue::AbstractPriorityQueue;
+
+        let mut q = IsraeliPriority::<MyU8, u8, u8>::with_capacity(4);
+        q.enqueue_batch([MyU8(0), MyU8(5), MyU8(10)], 7);
+        q.my_enqueue(MyU8(1), 3);
+        q.my_enqueue(MyU8(6), 4);
+        q.my_enqueue(MyU8(2), 5);
+        assert_eq!(q.num_waiting_groups(), 3);
+        assert_eq!(q.group_len(&0), Some(3));
+        assert_eq!(q.group_len(&1), Some(2));
+        assert_eq!(q.group_len(&3), None);
+        assert_eq!(q.group_priority(&1), Some(&4));
+        assert_eq!(q.waiting_rank(&0), Some(0));
+        assert_eq!(q.waiting_rank(&2), Some(1));
+        assert_eq!(q.waiting_rank(&1), Some(2));
+        assert_eq!(q.items_ahead(&1), Some(4));
+        assert!(!q.is_in_foyer(&0));
+
+        assert_eq!(q.my_dequeue(), Some((MyU8(10), 7)));
+        assert!(q.is_in_foyer(&0));
+        assert_eq!(q.num_waiting_groups(), 2);
+        assert_eq!(q.group_len(&0), Some(2));
+        assert_eq!(q.group_priority(&0), Some(&7));
+        assert_eq!(q.waiting_rank(&0), None);
+        assert_eq!(q.items_ahead(&0), Some(0));
+        assert_eq!(q.waiting_rank(&2), Some(0));
+        assert_eq!(q.items_ahead(&2), Some(2));
+        assert_eq!(q.items_ahead(&1), Some(3));
+        assert_eq!(q.my_len(), 5);
+    }
+
+    #[test]
+    fn remove_and_reprioritize_groups() {
+        use super::IsraeliPriority;
+        use crate::my_priority_queue::AbstractPriorityQueue;
+
+        let mut q = IsraeliPriority::<MyU8, u8, u8>::with_capacity(4);
+        q.enqueue_batch([MyU8(0), MyU8(5), MyU8(10)], 7);
+        q.enqueue_batch([MyU8(1), MyU8(6)], 3);
+        q.my_enqueue(MyU8(2), 5);
+        q.my_enqueue(MyU8(3), 1);
+
+        assert_eq!(q.set_group_priority(&1, 9), Some(3));
+        assert_eq!(q.set_group_priority(&4, 9), None);
+        assert_eq!(q.my_dequeue(), Some((MyU8(6), 9)));
+        assert_eq!(q.set_group_priority(&1, 0), Some(9));
+        assert_eq!(q.my_dequeue(), Some((MyU8(1), 0)));
+
+        let (removed, removed_priority) = q.remove_group(&2).expect("group 2 is waiting");
+        assert_eq!(removed.into_iter().collect::<Vec<_>>(), vec![MyU8(2)]);
+        assert_eq!(removed_priority, 5);
+        assert!(q.remove_group(&2).is_none());
+        assert_eq!(q.my_len(), 4);
+
+        assert_eq!(q.my_dequeue(), Some((MyU8(10), 7)));
+        let (removed, removed_priority) = q.remove_group(&0).expect("group 0 is in the foyer");
+        assert_eq!(removed.len(), 2);
+        assert_eq!(removed_priority, 7);
+        assert_eq!(q.my_len(), 1);
+        assert_eq!(q.drain_all(), vec![(MyU8(3), 1)]);
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn shibboleth_once_per_item() {
+        use super::{FoyerPreemption, IsraeliPriority};
+        use crate::my_priority_queue::AbstractPriorityQueue;
+        use std::cell::Cell;
+
+        thread_local! {
+            static SHIBBOLETH_CALLS: Cell<usize> = const { Cell::new(0) };
+        }
+
+        #[derive(PartialEq, Eq, Debug)]
+        struct Counted(u8);
+        impl Friendly<u8> for Counted {
+            fn friendship_shibboleth(&self) -> u8 {
+                SHIBBOLETH_CALLS.with(|c| c.set(c.get() + 1));
+                self.0 % MY_U8_FREINDLINESS
+            }
+        }
+
+        let mut q = IsraeliPriority::<Counted, u8, u8>::with_capacity(8);
+        q.change_foyer_preemption(FoyerPreemption::AtLeast(6));
+        let mut num_enqueued = 0;
+        for round in 0..10u8 {
+            q.enqueue_batch([0, 5, 10].map(|z| Counted(z + round)), round);
+            q.enqueue_batch([1, 2, 3, 4].map(|z| Counted(z * round)), 9 - round);
+            q.my_enqueue(Counted(round), round);
+            num_enqueued += 8;
+            let _ = q.my_dequeue();
+            let _ = q.dequeue_batch(3, 4);
+            assert_eq!(SHIBBOLETH_CALLS.with(Cell::get), num_enqueued);
+        }
+        let _ = q.drain_all();
+        assert!(q.is_empty());
+        assert_eq!(SHIBBOLETH_CALLS.with(Cell::get), num_enqueued);
+    }
+
+    #[test]
+    fn mixed_batch_same_as_one_by_one() {
+        use super::{FoyerPreemption, IsraeliPriority};
+        use crate::my_priority_queue::AbstractPriorityQueue;
+
+        let batches: [(&[u8], u8); 6] = [
+            (&[0, 1, 5, 2, 6, 10, 11], 3),
+            (&[7, 3, 12, 15, 4], 5),
+            (&[20, 21, 22, 23, 24, 25], 1),
+            (&[8], 9),
+            (&[9, 13, 14, 16, 17, 18, 19, 26], 4),
+            (&[27, 28, 30, 31], 2),
+        ];
+        for preemption in [FoyerPreemption::Never, FoyerPreemption::AtLeast(5)] {
+            let mut q1 = IsraeliPriority::<MyU8, u8, u8>::with_capacity(8);
+            let mut q2 = IsraeliPriority::<MyU8, u8, u8>::with_capacity(8);
+            q1.change_foyer_preemption(preemption.clone());
+            q2.change_foyer_preemption(preemption);
+            for (which_batch, (batch, batch_priority)) in batches.iter().enumerate() {
+                q1.enqueue_batch(batch.iter().map(|z| MyU8(*z)), *batch_priority);
+                for z in *batch {
+                    q2.my_enqueue(MyU8(*z), *batch_priority);
+                }
+                assert_eq!(q1.my_len(), q2.my_len());
+                assert_eq!(q1.num_waiting_groups(), q2.num_waiting_groups());
+                for shibboleth in 0..MY_U8_FREINDLINESS {
+                    assert_eq!(q1.group_len(&shibboleth), q2.group_len(&shibboleth));
+                    assert_eq!(
+                        q1.group_priority(&shibboleth),
+                        q2.group_priority(&shibboleth)
+                    );
+                    assert_eq!(q1.waiting_rank(&shibboleth), q2.waiting_rank(&shibboleth));
+                }
+                if which_batch % 2 == 1 {
+                    assert_eq!(q1.my_dequeue(), q2.my_dequeue());
+                }
+            }
+            assert_eq!(q1.drain_all(), q2.drain_all());
+        }
+    }
 }
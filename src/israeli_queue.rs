@@ -0,0 +1,337 @@
+use nonempty::NonEmpty;
+use std::{hash::Hash, marker::PhantomData};
+
+use crate::friend_level::{default_combiner, PriorityCombiner};
+use crate::israeli_priority::Friendly;
+use crate::my_priority_queue::{AbstractPriorityQueue, GivesBackPriorities};
+
+/// This is really an Israeli queue.
+/// A new item scans through the groups waiting in line
+/// and joins the highest priority one that has a friend of theirs according to `is_friend`.
+/// That costs time proportional to how many are waiting,
+/// but it works for friendship that has no hashable shibboleth like overlapping ranges.
+/// Such items can implement `Friendly<()>` with a trivial shibboleth and override `is_friend`.
+pub struct IsraeliQueue<T, P, H = ()>
+where
+    T: Friendly<H>,
+    P: Ord + Clone,
+    H: Hash + Eq,
+{
+    waiting_groups: Vec<(NonEmpty<T>, P)>,
+    current_friend_group: Option<(NonEmpty<T>, P)>,
+    waiting_len: usize,
+    priority_combiner: PriorityCombiner<P>,
+    junk: PhantomData<H>,
+}
+
+impl<T, P, H> Default for IsraeliQueue<T, P, H>
+where
+    T: Friendly<H>,
+    P: Ord + Clone,
+    H: Hash + Eq,
+{
+    fn default() -> Self {
+        Self::with_capacity(8)
+    }
+}
+
+impl<T, P, H> IsraeliQueue<T, P, H>
+where
+    T: Friendly<H>,
+    P: Ord + Clone,
+    H: Hash + Eq,
+{
+    /// setup with capacity for specified number of distinct friend groups
+    /// and the same default way of combining priorities as `IsraeliPriority`
+    #[must_use]
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            waiting_groups: Vec::with_capacity(capacity),
+            current_friend_group: None,
+            waiting_len: 0,
+            priority_combiner: default_combiner,
+            junk: PhantomData,
+        }
+    }
+
+    /// provide a different way for priorities to combine
+    /// when a portion is already in line and a new friend joins
+    /// that friend group
+    pub fn change_combiner(&mut self, new_combiner: PriorityCombiner<P>) {
+        self.priority_combiner = new_combiner;
+    }
+
+    /// where in `waiting_groups` is the highest priority group
+    /// the earliest one to get in line wins ties
+    fn head_of_line(&self) -> Option<usize> {
+        let mut best: Option<(usize, &P)> = None;
+        for (idx, (_, group_priority)) in self.waiting_groups.iter().enumerate() {
+            if best.is_none_or(|(_, best_priority)| group_priority > best_priority) {
+                best = Some((idx, group_priority));
+            }
+        }
+        best.map(|(idx, _)| idx)
+    }
+
+    /// where in `waiting_groups` is the highest priority group
+    /// with at least one friend of `new_obj`
+    fn find_friends(&self, new_obj: &T) -> Option<usize> {
+        let mut best: Option<(usize, &P)> = None;
+        for (idx, (group, group_priority)) in self.waiting_groups.iter().enumerate() {
+            if best.is_none_or(|(_, best_priority)| group_priority > best_priority)
+                && group.iter().any(|friend| friend.is_friend(new_obj))
+            {
+                best = Some((idx, group_priority));
+            }
+        }
+        best.map(|(idx, _)| idx)
+    }
+
+    /// the same as `IsraeliPriority`
+    /// either the whole group in the ``foyer`` or the next group in line
+    fn israeli_dequeue_batch(&mut self) -> Option<(NonEmpty<T>, P)> {
+        if let Some(current_batch) = self.current_friend_group.take() {
+            Some(current_batch)
+        } else {
+            let first_in_next_batch = self.my_dequeue();
+            if let Some((rest_of_batch, priority)) = self.current_friend_group.take() {
+                let real_first =
+                    first_in_next_batch.expect("If there was a rest, then there would be a first");
+                let mut ret_val = NonEmpty::singleton(real_first.0);
+                ret_val.extend(rest_of_batch);
+                Some((ret_val, priority))
+            } else {
+                first_in_next_batch.map(|(z, w)| (NonEmpty::singleton(z), w))
+            }
+        }
+    }
+}
+
+impl<T, P, H> AbstractPriorityQueue<T, P> for IsraeliQueue<T, P, H>
+where
+    T: Friendly<H>,
+    P: Ord + Clone,
+    H: Hash + Eq,
+{
+    fn empty_copy(&self) -> Self {
+        let mut to_return = Self::with_capacity(self.waiting_groups.len());
+        to_return.change_combiner(self.priority_combiner);
+        to_return
+    }
+
+    fn my_peek(&self) -> Option<(&T, &P)> {
+        match &self.current_friend_group {
+            Some((real_group, top_priority)) => Some((real_group.last(), top_priority)),
+            None => self.head_of_line().map(|idx| {
+                let (group, group_priority) = &self.waiting_groups[idx];
+                (group.last(), group_priority)
+            }),
+        }
+    }
+
+    fn my_enqueue(&mut self, new_obj: T, new_obj_priority: P) {
+        if let Some(idx) = self.find_friends(&new_obj) {
+            // found friends waiting in line
+            let (friend_grp, old_priority) = &mut self.waiting_groups[idx];
+            let (priority_changed, new_priority) =
+                (self.priority_combiner)(old_priority, &new_obj_priority);
+            if priority_changed {
+                // still can't push them up to be past the group that is currently entering
+                *old_priority = new_priority;
+            }
+            friend_grp.push(new_obj);
+            self.waiting_len += 1;
+        } else if let Some(head_group) = self
+            .current_friend_group
+            .as_mut()
+            .filter(|(head_group, _)| head_group.iter().any(|friend| friend.is_friend(&new_obj)))
+        {
+            // they are your friends and they are the ones just going in now
+            let (priority_changed, new_priority) =
+                (self.priority_combiner)(&head_group.1, &new_obj_priority);
+            if priority_changed {
+                head_group.1 = new_priority;
+            }
+            head_group.0.push(new_obj);
+        } else {
+            // no friends anywhere, go to the back of the line
+            self.waiting_groups
+                .push((NonEmpty::singleton(new_obj), new_obj_priority));
+            self.waiting_len += 1;
+        }
+    }
+
+    fn enqueue_batch(&mut self, new_batch: impl IntoIterator<Item = T>, new_batch_priority: P) {
+        for z in new_batch {
+            self.my_enqueue(z, new_batch_priority.clone());
+        }
+    }
+
+    fn my_dequeue(&mut self) -> Option<(T, P)> {
+        if let Some((mut head_group, head_priority)) = self.current_friend_group.take() {
+            if head_group.len() == 1 {
+                Some((head_group.head, head_priority))
+            } else {
+                let ret_val = head_group.pop().map(|z| (z, head_priority.clone()));
+                self.current_friend_group = Some((head_group, head_priority));
+                ret_val
+            }
+        } else if let Some(idx) = self.head_of_line() {
+            // the next batch becomes the currently processing group
+            let new_head_of_line = self.waiting_groups.remove(idx);
+            self.waiting_len -= new_head_of_line.0.len();
+            self.current_friend_group = Some(new_head_of_line);
+            self.my_dequeue()
+        } else {
+            // the entire line is empty
+            None
+        }
+    }
+
+    fn dequeue_batch(&mut self, around_how_many: usize, hard_limit: usize) -> Vec<(T, P)> {
+        assert!(hard_limit >= around_how_many);
+        let mut to_return = Vec::with_capacity(around_how_many);
+        while to_return.len() < around_how_many {
+            if let Some((real_put_in, put_in_priority)) = self.israeli_dequeue_batch() {
+                to_return.extend(
+                    real_put_in
+                        .into_iter()
+                        .map(|z| (z, put_in_priority.clone())),
+                );
+            } else {
+                break;
+            }
+        }
+        if to_return.len() > hard_limit {
+            // the last friend group was too big, some of them go back to the head of the line
+            // they are all from that last group because we stop as soon as we have `around_how_many`
+            let left_back = to_return.split_off(hard_limit);
+            if let Some(nonempty_left_back) = NonEmpty::from_vec(left_back) {
+                let left_back_priority = nonempty_left_back.head.1.clone();
+                self.current_friend_group =
+                    Some((nonempty_left_back.map(|(z, _)| z), left_back_priority));
+            }
+        }
+        to_return
+    }
+
+    fn my_len(&self) -> usize {
+        let at_head_len = self.current_friend_group.as_ref().map_or(0, |z| z.0.len());
+        at_head_len + self.waiting_len
+    }
+
+    fn is_empty(&self) -> bool {
+        self.waiting_len == 0 && self.current_friend_group.is_none()
+    }
+}
+
+/// the priority of the group they were in
+impl<T, P, H> GivesBackPriorities<T, P> for IsraeliQueue<T, P, H>
+where
+    T: Friendly<H>,
+    P: Ord + Clone,
+    H: Hash + Eq,
+{
+}
+
+mod test {
+    use crate::{AbstractPriorityQueue, Friendly};
+
+    const MY_U8_FREINDLINESS: u8 = 5;
+
+    #[allow(dead_code)]
+    #[derive(PartialEq, Eq, Debug)]
+    #[repr(transparent)]
+    struct MyU8(u8);
+    impl Friendly<u8> for MyU8 {
+        fn friendship_shibboleth(&self) -> u8 {
+            self.0 % MY_U8_FREINDLINESS
+        }
+    }
+
+    /// closed ranges which are friends when they overlap
+    #[allow(dead_code)]
+    #[derive(PartialEq, Eq, Debug)]
+    struct Span(u8, u8);
+    impl Friendly<()> for Span {
+        fn is_friend(&self, other: &Self) -> bool {
+            self.0 <= other.1 && other.0 <= self.1
+        }
+
+        fn friendship_shibboleth(&self) {}
+    }
+
+    /// every item has a different priority, so the group priorities never tie
+    /// and the result does not depend on how ties are broken
+    #[allow(dead_code)]
+    fn interleaved_scenario<Q: AbstractPriorityQueue<MyU8, u8>>(mut q: Q) -> Vec<(MyU8, u8)> {
+        let mut to_return = Vec::new();
+        let mut step = 0u8;
+        for round in 0..8u8 {
+            for _ in 0..=round {
+                step = step.wrapping_add(53);
+                q.my_enqueue(MyU8(step), step);
+            }
+            if round % 3 == 2 {
+                to_return.extend(q.dequeue_batch(3, 4));
+            } else {
+                to_return.extend(q.my_dequeue());
+            }
+        }
+        to_return.extend(q.drain_all());
+        assert!(q.is_empty());
+        to_return
+    }
+
+    #[test]
+    fn nothing_in_out() {
+        use super::IsraeliQueue;
+
+        let mut q = IsraeliQueue::<Span, u8>::with_capacity(2);
+        assert_eq!(q.my_len(), 0);
+        assert!(q.is_empty());
+        assert!(q.my_peek().is_none());
+        assert!(q.my_dequeue().is_none());
+        assert!(q.dequeue_batch(10, 10).is_empty());
+    }
+
+    #[test]
+    fn agrees_with_shibboleth() {
+        use super::IsraeliQueue;
+        use crate::IsraeliPriority;
+
+        let scanned = interleaved_scenario(IsraeliQueue::<MyU8, u8, u8>::with_capacity(8));
+        let hashed = interleaved_scenario(IsraeliPriority::<MyU8, u8, u8>::with_capacity(8));
+        assert_eq!(scanned.len(), (1..=8).sum::<usize>());
+        assert_eq!(scanned, hashed);
+    }
+
+    #[test]
+    fn overlapping_spans() {
+        use super::IsraeliQueue;
+
+        let mut q = IsraeliQueue::<Span, u8>::with_capacity(4);
+        q.my_enqueue(Span(0, 2), 1);
+        q.my_enqueue(Span(5, 6), 3);
+        q.my_enqueue(Span(10, 12), 2);
+        // friends with the first span, so that group jumps to priority 4
+        q.my_enqueue(Span(2, 3), 4);
+        assert_eq!(q.my_len(), 4);
+        assert_eq!(q.my_peek(), Some((&Span(2, 3), &4)));
+        assert_eq!(q.my_dequeue(), Some((Span(2, 3), 4)));
+        // friends with the group in the ``foyer``
+        q.my_enqueue(Span(1, 1), 0);
+        // not friends with anyone
+        q.my_enqueue(Span(8, 8), 9);
+        assert_eq!(
+            q.drain_all(),
+            vec![
+                (Span(0, 2), 4),
+                (Span(1, 1), 4),
+                (Span(8, 8), 9),
+                (Span(5, 6), 3),
+                (Span(10, 12), 2)
+            ]
+        );
+    }
+}
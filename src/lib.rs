@@ -1,20 +1,54 @@
+pub mod boxed_queue;
+pub mod coarse_grainers;
 #[cfg(feature = "dependency")]
 pub mod dependency_queue;
 
+pub mod either_queue;
+mod friend_level;
+pub mod hierarchical_bitmap;
+pub mod hierarchical_israeli;
+pub mod israeli_of_queues;
 pub mod israeli_priority;
+pub mod israeli_queue;
 pub mod iterator_manipulation;
 pub mod my_priority_queue;
 pub mod nested_queue;
 pub mod no_priority_queue;
 pub mod ordinary_priority_queue;
+pub mod radix_heap;
+pub mod round_robin;
+pub mod topology;
+#[cfg(feature = "config")]
+pub mod topology_config;
 
-pub use israeli_priority::{Friendly, IsraeliPriority};
+pub use boxed_queue::{BoxedQueue, DynPriorityQueue};
+pub use coarse_grainers::{
+    DeadlineBucket, DivideBy, DurationBucket, InstantBucket, InstantGrainer, Log2Bucket, TopBits,
+};
+pub use either_queue::EitherQueue;
+pub use friend_level::PriorityCombiner;
+pub use hierarchical_bitmap::HierarchicalBitmap;
+pub use hierarchical_israeli::{HierarchicalIsraeliPriority, HierarchicallyFriendly};
+pub use israeli_of_queues::IsraeliOfQueues;
+pub use israeli_priority::{CheckedDifference, FoyerPreemption, Friendly, IsraeliPriority};
+pub use israeli_queue::IsraeliQueue;
 pub use iterator_manipulation::{
     Feedback, FeedbackResult, FlushableIterator, Reorderable, Reordered,
 };
-pub use my_priority_queue::AbstractPriorityQueue;
-pub use nested_queue::BucketQueue;
+pub use my_priority_queue::{AbstractPriorityQueue, GivesBackPriorities};
+pub use nested_queue::{
+    BucketFactory, BucketQueue, ByCoarseGrainedPriority, CoarseGrainedPriority, CoarseGrainer,
+    OccupancyIndex, RingBuckets,
+};
 pub use no_priority_queue::NoPriorityQueue;
+pub use radix_heap::{NotMonotone, RadixHeap, RadixKey};
+pub use round_robin::{RoundRobinIsraeli, RoundRobinTurns};
+pub use topology::{BucketQueueBuilder, IsraeliPriorityBuilder};
+#[cfg(feature = "config")]
+pub use topology_config::{CoarseConfig, CombinerConfig, ConfigSupport, QueueConfig, Unsupported};
 
 #[cfg(feature = "dependency")]
-pub use dependency_queue::{Blocker, BlockingQueue};
+pub use dependency_queue::{
+    Blocker, BlockingQueue, ByResourceKeys, DependencyIndex, FailurePolicy, InconsistencyKind,
+    Inconsistent, KeyedDependencies, Lease, Pairwise, ResourceKeys, Validation,
+};
@@ -79,3 +79,8 @@ pub trait AbstractPriorityQueue<T, P: Ord> {
         self.dequeue_batch(self.my_len(), self.my_len())
     }
 }
+
+/// the priorities that come out are the ones the items went in with
+/// or for friend groups the combined priority of the group
+/// rather than made up like `NoPriorityQueue` does
+pub trait GivesBackPriorities<T, P: Ord>: AbstractPriorityQueue<T, P> {}
@@ -1,10 +1,19 @@
-use crate::my_priority_queue::AbstractPriorityQueue;
-use std::{collections::HashMap, hash::Hash, marker::PhantomData};
+use crate::my_priority_queue::{AbstractPriorityQueue, GivesBackPriorities};
+use priority_queue::PriorityQueue;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    hash::Hash,
+    marker::PhantomData,
+    sync::Arc,
+};
 
 /// the fine grained priorities can be coarse grained into this type
 /// which means we have a monotone map
 pub trait CoarseGrainedPriority<P> {
     fn coarse_grain(p: &P) -> Self;
+    /// the next coarse grained priority down
+    /// the `BucketQueue` finds occupied buckets with its `OccupancyIndex` instead
     fn decrement(&mut self);
 }
 
@@ -118,172 +127,651 @@ impl<Q> IndexInto<usize, Q> for Vec<Option<Q>> {
     }
 }
 
+/// redirect to the corresponding methods in `BTreeMap`
+/// the buckets are kept in order of coarse grained priority
+impl<C, Q> IndexInto<C, Q> for BTreeMap<C, Q>
+where
+    C: Ord,
+{
+    fn new() -> Self {
+        BTreeMap::new()
+    }
+
+    fn get(&self, which: &C) -> Option<&Q> {
+        self.get(which)
+    }
+
+    fn get_mut(&mut self, which: &C) -> Option<&mut Q> {
+        self.get_mut(which)
+    }
+
+    fn insert(&mut self, which: C, value: Q) -> Option<Q> {
+        self.insert(which, value)
+    }
+
+    fn remove(&mut self, which: &C) -> Option<Q> {
+        self.remove(which)
+    }
+
+    fn values<'a>(&'a self) -> impl Iterator<Item = &'a Q>
+    where
+        Q: 'a,
+    {
+        self.values()
+    }
+}
+
+/// a fixed number `N` of slots used circularly
+/// so coarse grained priority `c` goes in slot `c % N`
+/// this is for monotone workloads like Dial's algorithm or a calendar queue
+/// where all the buckets in use at once fit in a window of `N` consecutive values
+pub struct RingBuckets<Q, const N: usize> {
+    slots: Vec<Option<(usize, Q)>>,
+}
+
+impl<Q, const N: usize> IndexInto<usize, Q> for RingBuckets<Q, N> {
+    fn new() -> Self {
+        const { assert!(N > 0, "there is at least one slot") };
+        Self {
+            slots: (0..N).map(|_| None).collect(),
+        }
+    }
+
+    fn get(&self, which: &usize) -> Option<&Q> {
+        match &self.slots[*which % N] {
+            Some((in_slot, q)) if *in_slot == *which => Some(q),
+            _ => None,
+        }
+    }
+
+    fn get_mut(&mut self, which: &usize) -> Option<&mut Q> {
+        match &mut self.slots[*which % N] {
+            Some((in_slot, q)) if *in_slot == *which => Some(q),
+            _ => None,
+        }
+    }
+
+    /// # Panics
+    /// if the slot is already in use by a different coarse grained priority
+    /// because the buckets in use did not fit in the window
+    fn insert(&mut self, which: usize, value: Q) -> Option<Q> {
+        let slot = &mut self.slots[which % N];
+        match slot.take() {
+            Some((in_slot, old_value)) if in_slot == which => {
+                *slot = Some((which, value));
+                Some(old_value)
+            }
+            Some((in_slot, _)) => {
+                panic!(
+                    "buckets {in_slot} and {which} are both in use but do not fit in a window of {N}"
+                );
+            }
+            None => {
+                *slot = Some((which, value));
+                None
+            }
+        }
+    }
+
+    fn remove(&mut self, which: &usize) -> Option<Q> {
+        let slot = &mut self.slots[*which % N];
+        if slot.as_ref().is_some_and(|(in_slot, _)| *in_slot == *which) {
+            slot.take().map(|(_, q)| q)
+        } else {
+            None
+        }
+    }
+
+    fn values<'a>(&'a self) -> impl Iterator<Item = &'a Q>
+    where
+        Q: 'a,
+    {
+        self.slots.iter().filter_map(|z| z.as_ref().map(|(_, q)| q))
+    }
+}
+
+/// keeps track of which coarse grained priorities currently have a nonempty bucket
+/// so that the next one can be found directly
+/// rather than going down one `decrement` at a time
+pub trait OccupancyIndex<C> {
+    fn new() -> Self;
+    fn occupy(&mut self, which: &C);
+    fn vacate(&mut self, which: &C);
+    fn highest_occupied(&self) -> Option<C>;
+    fn lowest_occupied(&self) -> Option<C>;
+    /// all the occupied ones from lowest to highest
+    fn all_occupied(&self) -> impl Iterator<Item = C> + '_;
+}
+
+/// an ordered set works for any coarse grained priority
+impl<C> OccupancyIndex<C> for BTreeSet<C>
+where
+    C: Ord + Clone,
+{
+    fn new() -> Self {
+        BTreeSet::new()
+    }
+
+    fn occupy(&mut self, which: &C) {
+        self.insert(which.clone());
+    }
+
+    fn vacate(&mut self, which: &C) {
+        self.remove(which);
+    }
+
+    fn highest_occupied(&self) -> Option<C> {
+        self.last().cloned()
+    }
+
+    fn lowest_occupied(&self) -> Option<C> {
+        self.first().cloned()
+    }
+
+    fn all_occupied(&self) -> impl Iterator<Item = C> + '_ {
+        self.iter().cloned()
+    }
+}
+
+/// how many dequeues each occupied bucket gets, rather than always the highest one
+/// each bucket is charged `FAIR_SHARE_STRIDE / weight` per item dequeued
+/// and the one that has been charged the least so far goes next, the higher one on ties
+/// a bucket that becomes occupied starts out charged as much as the last one to go
+/// so it does not get credit for the time it was empty
+struct FairShares<C>
+where
+    C: Hash + Ord + Clone,
+{
+    weight: fn(&C) -> usize,
+    charged: PriorityQueue<C, (Reverse<u64>, C)>,
+    virtual_time: u64,
+}
+
+const FAIR_SHARE_STRIDE: u64 = 1 << 32;
+
+impl<C> FairShares<C>
+where
+    C: Hash + Ord + Clone,
+{
+    fn new(weight: fn(&C) -> usize, already_occupied: impl Iterator<Item = C>) -> Self {
+        let mut to_return = Self {
+            weight,
+            charged: PriorityQueue::new(),
+            virtual_time: 0,
+        };
+        for which in already_occupied {
+            to_return.join(which);
+        }
+        to_return
+    }
+
+    fn join(&mut self, which: C) {
+        let virtual_time = self.virtual_time;
+        self.charged
+            .push(which.clone(), (Reverse(virtual_time), which));
+    }
+
+    fn leave(&mut self, which: &C) {
+        let _was_charged = self.charged.remove(which);
+    }
+
+    fn next_bucket(&self) -> Option<&C> {
+        self.charged.peek().map(|(which, _)| which)
+    }
+
+    fn charge(&mut self, which: &C, how_many: usize) {
+        let stride = FAIR_SHARE_STRIDE / ((self.weight)(which).max(1) as u64);
+        let how_many = how_many as u64;
+        let mut new_virtual_time = self.virtual_time;
+        let _was_charged = self
+            .charged
+            .change_priority_by(which, |(Reverse(so_far), _)| {
+                new_virtual_time = *so_far;
+                *so_far = so_far.saturating_add(stride.saturating_mul(how_many));
+            });
+        self.virtual_time = new_virtual_time;
+    }
+}
+
+/// how a particular `BucketQueue` coarse grains its priorities
+/// it should still be monotone
+/// closures work, for a coarse graining chosen at runtime
+pub trait CoarseGrainer<P, C> {
+    fn coarse_grain(&self, p: &P) -> C;
+}
+
+/// the default which is just `CoarseGrainedPriority` for the type C
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ByCoarseGrainedPriority;
+
+impl<P, C: CoarseGrainedPriority<P>> CoarseGrainer<P, C> for ByCoarseGrainedPriority {
+    fn coarse_grain(&self, p: &P) -> C {
+        C::coarse_grain(p)
+    }
+}
+
+impl<P, C, F: Fn(&P) -> C> CoarseGrainer<P, C> for F {
+    fn coarse_grain(&self, p: &P) -> C {
+        self(p)
+    }
+}
+
+/// makes the new bucket for a coarse grained priority
+/// so different buckets can use different kinds of queues, like with `EitherQueue`
+pub type BucketFactory<C, Q> = Arc<dyn Fn(&C) -> Q + Send + Sync>;
+
 /// the items of type T and priority P being stored are being stored in one of potentially
 /// several Q's which are all the same kind of `AbstractPriorityQueue` for such items and priorities
 /// but they are divided up by the coarsed grained priority
 /// so each one of those are smaller and only storing items with priorities with the same
 /// coarse grained priority
-pub struct BucketQueue<T, P, C, Q, Storer>
-where
-    C: CoarseGrainedPriority<P> + Hash + Ord + Clone,
+/// which of those buckets are nonempty is tracked by `Occupied`
+/// so finding the highest one does not depend on how spread out they are
+/// the coarse graining is `CoarseGrainedPriority` for C unless another `CoarseGrainer` is given
+pub struct BucketQueue<
+    T,
+    P,
+    C,
+    Q,
+    Storer,
+    Occupied = BTreeSet<C>,
+    Grainer = ByCoarseGrainedPriority,
+> where
+    C: Hash + Ord + Clone,
     P: Ord,
     Q: AbstractPriorityQueue<T, P>,
     Storer: IndexInto<C, Q>,
+    Occupied: OccupancyIndex<C>,
+    Grainer: CoarseGrainer<P, C>,
 {
     my_buckets: Storer,
+    occupied_buckets: Occupied,
+    fair_shares: Option<FairShares<C>>,
+    coarse_grainer: Grainer,
     upper_bound_occupied_bucket: C,
     lower_bound_occupied_bucket: C,
     junk: PhantomData<T>,
     junk2: PhantomData<P>,
     bucket_template: Q,
+    bucket_factory: Option<BucketFactory<C, Q>>,
+    bucket_pool: Vec<Q>,
+    bucket_pool_limit: usize,
 }
 
-impl<T, P, C, Q, Storer> Default for BucketQueue<T, P, C, Q, Storer>
+impl<T, P, C, Q, Storer, Occupied, Grainer> Default
+    for BucketQueue<T, P, C, Q, Storer, Occupied, Grainer>
 where
-    C: CoarseGrainedPriority<P> + Hash + Ord + Clone + Default,
+    C: Hash + Ord + Clone + Default,
     P: Ord,
     Q: AbstractPriorityQueue<T, P> + Default,
     Storer: IndexInto<C, Q>,
+    Occupied: OccupancyIndex<C>,
+    Grainer: CoarseGrainer<P, C> + Default,
 {
     fn default() -> Self {
         Self {
             my_buckets: Storer::new(),
+            occupied_buckets: Occupied::new(),
+            fair_shares: None,
+            coarse_grainer: Grainer::default(),
             upper_bound_occupied_bucket: C::default(),
             lower_bound_occupied_bucket: C::default(),
             junk: PhantomData,
             junk2: PhantomData,
             bucket_template: Q::default(),
+            bucket_factory: None,
+            bucket_pool: Vec::new(),
+            bucket_pool_limit: 0,
         }
     }
 }
 
-impl<T, P, C, Q, Storer> BucketQueue<T, P, C, Q, Storer>
+impl<T, P, C, Q, Storer, Occupied> BucketQueue<T, P, C, Q, Storer, Occupied>
 where
     C: CoarseGrainedPriority<P> + Hash + Ord + Clone,
     P: Ord,
     Q: AbstractPriorityQueue<T, P>,
     Storer: IndexInto<C, Q>,
+    Occupied: OccupancyIndex<C>,
 {
     /// pass a lower and upper bound for the coarse grained priorities you are expecting to see
     /// these don't have to be accurate, but if you pass them accurately it is better
     /// you also pass an example of Q that is to be used for constructing new buckets
     pub fn new(lower_bound_occupied_bucket: C, upper_bound_occupied_bucket: C, dummy: &Q) -> Self {
+        Self::with_coarse_grainer(
+            lower_bound_occupied_bucket,
+            upper_bound_occupied_bucket,
+            dummy,
+            ByCoarseGrainedPriority,
+        )
+    }
+}
+
+impl<T, P, C, Q, Storer, Occupied, Grainer> BucketQueue<T, P, C, Q, Storer, Occupied, Grainer>
+where
+    C: Hash + Ord + Clone,
+    P: Ord,
+    Q: AbstractPriorityQueue<T, P>,
+    Storer: IndexInto<C, Q>,
+    Occupied: OccupancyIndex<C>,
+    Grainer: CoarseGrainer<P, C>,
+{
+    /// the same as `new` but the priorities are coarse grained by `coarse_grainer`
+    /// so the granularity can be chosen at runtime
+    pub fn with_coarse_grainer(
+        lower_bound_occupied_bucket: C,
+        upper_bound_occupied_bucket: C,
+        dummy: &Q,
+        coarse_grainer: Grainer,
+    ) -> Self {
         Self {
             my_buckets: Storer::new(),
+            occupied_buckets: Occupied::new(),
+            fair_shares: None,
+            coarse_grainer,
             upper_bound_occupied_bucket,
             lower_bound_occupied_bucket,
             junk: PhantomData,
             junk2: PhantomData,
             bucket_template: dummy.empty_copy(),
+            bucket_factory: None,
+            bucket_pool: Vec::new(),
+            bucket_pool_limit: 0,
+        }
+    }
+
+    /// instead of always dequeueing from the highest occupied bucket
+    /// each occupied bucket gets a share of the dequeues proportional to `weight` of it
+    /// so that low buckets can not wait forever
+    /// a minimum share for the low buckets is given by weighting the high ones relative to them
+    /// the bucket's own queue still picks which of its items go
+    /// `None` goes back to always the highest first
+    pub fn change_bucket_weights(&mut self, weight: Option<fn(&C) -> usize>) {
+        self.fair_shares =
+            weight.map(|weight| FairShares::new(weight, self.occupied_buckets.all_occupied()));
+    }
+
+    /// the occupied buckets from lowest to highest
+    /// with how many items are in each
+    pub fn bucket_lens(&self) -> impl Iterator<Item = (C, usize)> + '_ {
+        self.occupied_buckets.all_occupied().map(|which| {
+            let bucket_len = self
+                .my_buckets
+                .get(&which)
+                .expect("occupied buckets exist")
+                .my_len();
+            (which, bucket_len)
+        })
+    }
+
+    /// how many items there are with each coarse grained priority
+    /// only the occupied ones are present
+    pub fn histogram(&self) -> BTreeMap<C, usize> {
+        self.bucket_lens().collect()
+    }
+
+    /// how many items are in the buckets at or above the one `priority` goes in
+    /// this counts whole buckets, not items, so it is an upper bound on how many are at or above `priority`
+    /// the items in the same bucket as `priority` all count even if their priority is below it
+    pub fn count_in_buckets_at_least(&self, priority: &P) -> usize {
+        let which_bucket = self.coarse_grainer.coarse_grain(priority);
+        self.bucket_lens()
+            .filter(|(which, _)| *which >= which_bucket)
+            .map(|(_, bucket_len)| bucket_len)
+            .sum()
+    }
+
+    /// the lower and upper bound for the occupied coarse grained priorities
+    /// these are exact whenever there is something in the queue
+    /// before the first enqueue they are what was passed in `new`
+    /// and once it is emptied out they stay at the last occupied buckets
+    pub fn occupied_bounds(&self) -> (&C, &C) {
+        (
+            &self.lower_bound_occupied_bucket,
+            &self.upper_bound_occupied_bucket,
+        )
+    }
+
+    /// switch to coarse graining by `new_coarse_grainer` and move all the items into their new buckets
+    /// each old bucket is drained in its own order and those items enqueued in that order
+    /// starting from the highest old bucket
+    /// so items from the same old bucket that share a new bucket stay in the same order
+    /// the new buckets are by the priorities the old buckets give back
+    /// so buckets like `NoPriorityQueue` that make them up can not be rebucketed
+    pub fn rebucket(&mut self, new_coarse_grainer: Grainer)
+    where
+        Q: GivesBackPriorities<T, P>,
+        Grainer: Clone,
+    {
+        let mut old_buckets = std::mem::replace(&mut self.my_buckets, Storer::new());
+        let mut old_occupied: Vec<C> = self.occupied_buckets.all_occupied().collect();
+        old_occupied.reverse();
+        self.occupied_buckets = Occupied::new();
+        if let Some(fair) = &mut self.fair_shares {
+            *fair = FairShares::new(fair.weight, std::iter::empty());
+        }
+        self.coarse_grainer = new_coarse_grainer;
+        for which in old_occupied {
+            let mut old_bucket = old_buckets.remove(&which).expect("occupied buckets exist");
+            for (z, w) in old_bucket.drain_all() {
+                self.my_enqueue(z, w);
+            }
+            self.recycle(old_bucket);
+        }
+    }
+
+    /// new buckets are made by `bucket_factory` for their coarse grained priority
+    /// instead of copying the example given in `new`
+    /// `None` goes back to copying that example
+    pub fn change_bucket_factory(&mut self, bucket_factory: Option<BucketFactory<C, Q>>) {
+        self.bucket_factory = bucket_factory;
+        self.bucket_pool.clear();
+    }
+
+    /// keep up to `bucket_pool_limit` emptied buckets around
+    /// and reuse them for new coarse grained priorities instead of making new ones
+    /// so whatever they had allocated is not lost
+    /// this is only for buckets that are as good as new once they are empty
+    /// and it is not used with a bucket factory, because it might make different kinds for different priorities
+    /// 0 is the default which turns this off
+    pub fn change_bucket_pool_limit(&mut self, bucket_pool_limit: usize) {
+        self.bucket_pool_limit = bucket_pool_limit;
+        self.bucket_pool.truncate(bucket_pool_limit);
+    }
+
+    /// how many emptied buckets are waiting to be reused
+    pub fn bucket_pool_len(&self) -> usize {
+        self.bucket_pool.len()
+    }
+
+    /// an emptied bucket goes in the pool if there is room
+    fn recycle(&mut self, emptied: Q) {
+        if self.bucket_factory.is_none() && self.bucket_pool.len() < self.bucket_pool_limit {
+            self.bucket_pool.push(emptied);
+        }
+    }
+
+    /// an empty bucket for the coarse grained priority `which_bucket`
+    fn new_bucket(&mut self, which_bucket: &C) -> Q {
+        match &self.bucket_factory {
+            Some(bucket_factory) => bucket_factory(which_bucket),
+            None => self
+                .bucket_pool
+                .pop()
+                .unwrap_or_else(|| self.bucket_template.empty_copy()),
+        }
+    }
+
+    /// which bucket gets the next dequeue
+    fn next_bucket(&self) -> Option<C> {
+        match &self.fair_shares {
+            Some(fair) => fair.next_bucket().cloned(),
+            None => self.occupied_buckets.highest_occupied(),
+        }
+    }
+
+    /// the bucket `which_bucket` now has something in it but did not before
+    fn occupy(&mut self, which_bucket: &C) {
+        self.occupied_buckets.occupy(which_bucket);
+        if let Some(fair) = &mut self.fair_shares {
+            fair.join(which_bucket.clone());
+        }
+    }
+
+    /// the bucket `which_bucket` is about to get something put in it
+    /// if nothing is occupied yet, the bounds start over from it
+    fn widen_bounds(&mut self, which_bucket: &C) {
+        if self.occupied_buckets.highest_occupied().is_none() {
+            self.lower_bound_occupied_bucket = which_bucket.clone();
+            self.upper_bound_occupied_bucket = which_bucket.clone();
+            return;
+        }
+        if *which_bucket < self.lower_bound_occupied_bucket {
+            self.lower_bound_occupied_bucket = which_bucket.clone();
+        }
+        if *which_bucket > self.upper_bound_occupied_bucket {
+            self.upper_bound_occupied_bucket = which_bucket.clone();
+        }
+    }
+
+    /// the bucket `which_bucket` might have just been emptied out
+    /// if so it is removed
+    fn vacate_if_empty(&mut self, which_bucket: &C) {
+        let now_empty = self
+            .my_buckets
+            .get(which_bucket)
+            .is_some_and(AbstractPriorityQueue::is_empty);
+        if now_empty {
+            if let Some(emptied) = self.my_buckets.remove(which_bucket) {
+                self.recycle(emptied);
+            }
+            self.occupied_buckets.vacate(which_bucket);
+            if let Some(fair) = &mut self.fair_shares {
+                fair.leave(which_bucket);
+            }
+            if let Some(highest) = self.occupied_buckets.highest_occupied() {
+                self.upper_bound_occupied_bucket = highest;
+            }
+            if let Some(lowest) = self.occupied_buckets.lowest_occupied() {
+                self.lower_bound_occupied_bucket = lowest;
+            }
         }
     }
 }
 
-impl<T, P, C, Q, Storer> AbstractPriorityQueue<T, P> for BucketQueue<T, P, C, Q, Storer>
+impl<T, P, C, Q, Storer, Occupied, Grainer> AbstractPriorityQueue<T, P>
+    for BucketQueue<T, P, C, Q, Storer, Occupied, Grainer>
 where
-    C: CoarseGrainedPriority<P> + Hash + Ord + Clone,
+    C: Hash + Ord + Clone,
     P: Ord,
     Q: AbstractPriorityQueue<T, P>,
     Storer: IndexInto<C, Q>,
+    Occupied: OccupancyIndex<C>,
+    Grainer: CoarseGrainer<P, C> + Clone,
 {
     fn empty_copy(&self) -> Self {
         Self {
             my_buckets: Storer::new(),
+            occupied_buckets: Occupied::new(),
+            fair_shares: self
+                .fair_shares
+                .as_ref()
+                .map(|fair| FairShares::new(fair.weight, std::iter::empty())),
+            coarse_grainer: self.coarse_grainer.clone(),
             upper_bound_occupied_bucket: self.upper_bound_occupied_bucket.clone(),
             lower_bound_occupied_bucket: self.lower_bound_occupied_bucket.clone(),
             junk: self.junk,
             junk2: self.junk2,
             bucket_template: self.bucket_template.empty_copy(),
+            bucket_factory: self.bucket_factory.clone(),
+            bucket_pool: Vec::new(),
+            bucket_pool_limit: self.bucket_pool_limit,
         }
     }
 
     fn my_peek(&self) -> Option<(&T, &P)> {
-        let mut looking_in_bucket = self.upper_bound_occupied_bucket.clone();
-        while looking_in_bucket >= self.lower_bound_occupied_bucket {
-            if let Some(cur_bucket) = self.my_buckets.get(&looking_in_bucket) {
-                #[allow(clippy::single_match)]
-                match cur_bucket.my_peek() {
-                    z @ Some(_) => {
-                        return z;
-                    }
-                    None => {}
-                }
-            }
-            looking_in_bucket.decrement();
-        }
-        None
+        let next_bucket = self.next_bucket()?;
+        self.my_buckets
+            .get(&next_bucket)
+            .expect("occupied buckets exist")
+            .my_peek()
     }
 
     fn my_enqueue(&mut self, new_obj: T, new_obj_priority: P) {
-        let which_bucket = C::coarse_grain(&new_obj_priority);
-        self.lower_bound_occupied_bucket = std::cmp::min(
-            self.lower_bound_occupied_bucket.clone(),
-            which_bucket.clone(),
-        );
-        self.upper_bound_occupied_bucket = std::cmp::max(
-            self.upper_bound_occupied_bucket.clone(),
-            which_bucket.clone(),
-        );
+        let which_bucket = self.coarse_grainer.coarse_grain(&new_obj_priority);
+        self.widen_bounds(&which_bucket);
         if let Some(cur_bucket) = self.my_buckets.get_mut(&which_bucket) {
             cur_bucket.my_enqueue(new_obj, new_obj_priority);
         } else {
-            let mut new_bucket = self.bucket_template.empty_copy();
+            let mut new_bucket = self.new_bucket(&which_bucket);
             new_bucket.my_enqueue(new_obj, new_obj_priority);
+            self.occupy(&which_bucket);
             self.my_buckets.insert(which_bucket, new_bucket);
         }
     }
 
     fn enqueue_batch(&mut self, new_batch: impl IntoIterator<Item = T>, new_batch_priority: P) {
-        let which_bucket = C::coarse_grain(&new_batch_priority);
-        self.lower_bound_occupied_bucket = std::cmp::min(
-            self.lower_bound_occupied_bucket.clone(),
-            which_bucket.clone(),
-        );
-        self.upper_bound_occupied_bucket = std::cmp::max(
-            self.upper_bound_occupied_bucket.clone(),
-            which_bucket.clone(),
-        );
+        let which_bucket = self.coarse_grainer.coarse_grain(&new_batch_priority);
         if let Some(cur_bucket) = self.my_buckets.get_mut(&which_bucket) {
             cur_bucket.enqueue_batch(new_batch, new_batch_priority);
         } else {
-            let mut new_bucket = self.bucket_template.empty_copy();
+            let mut new_bucket = self.new_bucket(&which_bucket);
             new_bucket.enqueue_batch(new_batch, new_batch_priority);
+            if new_bucket.is_empty() {
+                // nothing was actually in the batch
+                return;
+            }
+            self.widen_bounds(&which_bucket);
+            self.occupy(&which_bucket);
             self.my_buckets.insert(which_bucket, new_bucket);
         }
     }
 
     fn my_dequeue(&mut self) -> Option<(T, P)> {
-        while self.upper_bound_occupied_bucket >= self.lower_bound_occupied_bucket {
-            if let Some(cur_bucket) = self.my_buckets.get_mut(&self.upper_bound_occupied_bucket) {
-                let ret_item = cur_bucket.my_dequeue();
-                if cur_bucket.is_empty() {
-                    let _is_cur_bucket = self.my_buckets.remove(&self.upper_bound_occupied_bucket);
-                    self.upper_bound_occupied_bucket.decrement();
-                }
-                return ret_item;
-            }
-            self.upper_bound_occupied_bucket.decrement();
+        let next_bucket = self.next_bucket()?;
+        let ret_item = self
+            .my_buckets
+            .get_mut(&next_bucket)
+            .expect("occupied buckets exist")
+            .my_dequeue();
+        if let Some(fair) = &mut self.fair_shares {
+            fair.charge(&next_bucket, 1);
         }
-        None
+        self.vacate_if_empty(&next_bucket);
+        ret_item
     }
 
     fn dequeue_batch(&mut self, around_how_many: usize, hard_limit: usize) -> Vec<(T, P)> {
         let mut to_return = Vec::with_capacity(around_how_many);
         let mut to_return_len = 0;
-        while self.upper_bound_occupied_bucket >= self.lower_bound_occupied_bucket {
-            if let Some(cur_bucket) = self.my_buckets.get_mut(&self.upper_bound_occupied_bucket) {
-                let this_batch = cur_bucket
-                    .dequeue_batch(around_how_many - to_return_len, hard_limit - to_return_len);
-                to_return_len += this_batch.len();
-                to_return.extend(this_batch);
-                if cur_bucket.is_empty() {
-                    let _is_cur_bucket = self.my_buckets.remove(&self.upper_bound_occupied_bucket);
-                }
-                if to_return_len >= around_how_many {
-                    break;
-                }
+        while to_return_len < around_how_many {
+            let Some(next_bucket) = self.next_bucket() else {
+                break;
+            };
+            let cur_bucket = self
+                .my_buckets
+                .get_mut(&next_bucket)
+                .expect("occupied buckets exist");
+            // with fair shares, each bucket only gets one turn at a time
+            let this_turn = if self.fair_shares.is_some() {
+                1
+            } else {
+                around_how_many - to_return_len
+            };
+            let this_batch = cur_bucket.dequeue_batch(this_turn, hard_limit - to_return_len);
+            let this_batch_len = this_batch.len();
+            to_return_len += this_batch_len;
+            to_return.extend(this_batch);
+            let emptied = cur_bucket.is_empty();
+            if let Some(fair) = &mut self.fair_shares {
+                fair.charge(&next_bucket, this_batch_len);
+            }
+            self.vacate_if_empty(&next_bucket);
+            if !emptied && (self.fair_shares.is_none() || this_batch_len == 0) {
+                // that bucket had enough to give
+                // or it could not give anything without going over `hard_limit`
+                break;
             }
-            self.upper_bound_occupied_bucket.decrement();
         }
         to_return
     }
@@ -297,13 +785,324 @@ where
     }
 
     fn is_empty(&self) -> bool {
-        for v in self.my_buckets.values() {
-            if !v.is_empty() {
-                return false;
+        self.occupied_buckets.highest_occupied().is_none()
+    }
+}
+
+impl<T, P, C, Q, Storer, Occupied, Grainer> GivesBackPriorities<T, P>
+    for BucketQueue<T, P, C, Q, Storer, Occupied, Grainer>
+where
+    C: Hash + Ord + Clone,
+    P: Ord,
+    Q: GivesBackPriorities<T, P>,
+    Storer: IndexInto<C, Q>,
+    Occupied: OccupancyIndex<C>,
+    Grainer: CoarseGrainer<P, C> + Clone,
+{
+}
+
+mod test {
+    use crate::my_priority_queue::{AbstractPriorityQueue, GivesBackPriorities};
+
+    /// a monotone workload where the buckets in use stay in a small window
+    /// which moves down as the highest ones are dequeued
+    #[allow(dead_code)]
+    fn monotone_workload<Q: AbstractPriorityQueue<(usize, usize), usize>>(
+        mut q: Q,
+    ) -> Vec<(usize, usize)> {
+        let mut to_return = Vec::new();
+        let mut step = 3usize;
+        for round in 0..60 {
+            let base = 400 - 3 * round;
+            for idx in 0..4 {
+                step = step.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+                let priority = base - (step >> 40) % 8;
+                q.my_enqueue((priority, round * 4 + idx), priority);
+            }
+            if round % 2 == 0 {
+                to_return.extend(q.dequeue_batch(4, 4).into_iter().map(|(z, _)| z));
+            } else {
+                to_return.extend(q.my_dequeue().map(|(z, _)| z));
+                to_return.extend(q.my_dequeue().map(|(z, _)| z));
+                to_return.extend(q.dequeue_batch(2, 2).into_iter().map(|(z, _)| z));
             }
         }
-        true
+        to_return.extend(q.drain_all().into_iter().map(|(z, _)| z));
+        assert!(q.is_empty());
+        to_return
     }
-}
 
-mod test {}
+    #[test]
+    fn storers_agree() {
+        use super::{BucketQueue, RingBuckets};
+        use crate::no_priority_queue::NoPriorityQueue;
+        use std::collections::{BTreeMap, HashMap};
+
+        type Bucket = NoPriorityQueue<(usize, usize), usize>;
+        let with_hashmap = monotone_workload(BucketQueue::<
+            _,
+            _,
+            usize,
+            Bucket,
+            HashMap<usize, Bucket>,
+        >::new(0, 0, &Bucket::new()));
+        let with_btreemap = monotone_workload(BucketQueue::<
+            _,
+            _,
+            usize,
+            Bucket,
+            BTreeMap<usize, Bucket>,
+        >::new(0, 0, &Bucket::new()));
+        let with_vec = monotone_workload(
+            BucketQueue::<_, _, usize, Bucket, Vec<Option<Bucket>>>::new(0, 0, &Bucket::new()),
+        );
+        let with_ring = monotone_workload(BucketQueue::<
+            _,
+            _,
+            usize,
+            Bucket,
+            RingBuckets<Bucket, 32>,
+        >::new(0, 0, &Bucket::new()));
+        assert_eq!(with_hashmap.len(), 240);
+        assert_eq!(with_hashmap, with_btreemap);
+        assert_eq!(with_hashmap, with_vec);
+        assert_eq!(with_hashmap, with_ring);
+    }
+
+    #[test]
+    #[should_panic(expected = "do not fit in a window of 4")]
+    fn ring_too_small() {
+        use super::{BucketQueue, RingBuckets};
+        use crate::no_priority_queue::NoPriorityQueue;
+
+        type Bucket = NoPriorityQueue<usize, usize>;
+        let mut q =
+            BucketQueue::<_, _, usize, Bucket, RingBuckets<Bucket, 4>>::new(0, 0, &Bucket::new());
+        q.my_enqueue(1, 1);
+        q.my_enqueue(5, 5);
+    }
+
+    #[test]
+    fn weighted_buckets() {
+        use super::BucketQueue;
+        use crate::no_priority_queue::NoPriorityQueue;
+        use std::collections::HashMap;
+
+        type Bucket = NoPriorityQueue<usize, usize>;
+        let mut q =
+            BucketQueue::<_, _, usize, Bucket, HashMap<usize, Bucket>>::new(0, 0, &Bucket::new());
+        q.enqueue_batch(0..40, 9);
+        q.enqueue_batch(100..140, 1);
+        // always the highest first
+        assert!(q.dequeue_batch(4, 4).into_iter().all(|(z, _)| z < 40));
+        q.change_bucket_weights(Some(|c| if *c == 9 { 3 } else { 1 }));
+        let low_count = |out: &[(usize, usize)]| out.iter().filter(|(z, _)| *z >= 100).count();
+        let mut one_by_one = Vec::new();
+        for _ in 0..20 {
+            one_by_one.extend(q.my_dequeue());
+        }
+        assert_eq!(low_count(&one_by_one), 5);
+        let batched = q.dequeue_batch(20, 20);
+        assert_eq!(low_count(&batched), 5);
+        // a new bucket does not get credit for the time it was empty
+        q.my_enqueue(200, 5);
+        let all_out: Vec<_> = q.dequeue_batch(3, 3).into_iter().map(|(z, _)| z).collect();
+        assert!(all_out.contains(&200));
+        assert_eq!(q.my_len(), 34);
+        // the bucket's own queue still orders inside it
+        let rest = q.drain_all();
+        let low_ones: Vec<_> = rest.iter().map(|(z, _)| *z).filter(|z| *z >= 100).collect();
+        assert!(low_ones.windows(2).all(|w| w[0] < w[1]));
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn introspection() {
+        use super::{BucketQueue, CoarseGrainedPriority};
+        use crate::coarse_grainers::DivideBy;
+        use crate::no_priority_queue::NoPriorityQueue;
+        use std::collections::{BTreeMap, HashMap};
+
+        type Bucket = NoPriorityQueue<usize, usize>;
+        type Coarse = DivideBy<usize, 10>;
+        let mut q = BucketQueue::<_, _, Coarse, Bucket, HashMap<Coarse, Bucket>>::new(
+            Coarse::coarse_grain(&0),
+            Coarse::coarse_grain(&100),
+            &Bucket::new(),
+        );
+        assert_eq!(
+            q.occupied_bounds(),
+            (&Coarse::coarse_grain(&0), &Coarse::coarse_grain(&100))
+        );
+        for z in [5, 15, 17, 42, 45, 48, 61] {
+            q.my_enqueue(z, z);
+        }
+        let lens: Vec<_> = q.bucket_lens().map(|(_, bucket_len)| bucket_len).collect();
+        assert_eq!(lens, vec![1, 2, 3, 1]);
+        let histogram = q.histogram();
+        assert_eq!(histogram.len(), 4);
+        assert_eq!(histogram.get(&Coarse::coarse_grain(&40)), Some(&3));
+        assert_eq!(histogram.values().sum::<usize>(), q.my_len());
+        // only 48 and 61 are at least 46, but all of the bucket for 40 to 49 counts
+        assert_eq!(q.count_in_buckets_at_least(&46), 4);
+        assert_eq!(q.count_in_buckets_at_least(&50), 1);
+        assert_eq!(q.count_in_buckets_at_least(&0), 7);
+        // the bounds passed in were loose but the first enqueue replaced them
+        assert_eq!(
+            q.occupied_bounds(),
+            (&Coarse::coarse_grain(&5), &Coarse::coarse_grain(&61))
+        );
+        assert_eq!(q.dequeue_batch(4, 4).len(), 4);
+        assert_eq!(
+            q.occupied_bounds(),
+            (&Coarse::coarse_grain(&5), &Coarse::coarse_grain(&17))
+        );
+        // the lower bound is tightened too
+        assert_eq!(q.my_dequeue().map(|(z, _)| z), Some(15));
+        assert_eq!(q.my_dequeue().map(|(z, _)| z), Some(17));
+        assert_eq!(
+            q.occupied_bounds(),
+            (&Coarse::coarse_grain(&5), &Coarse::coarse_grain(&5))
+        );
+        q.drain_all();
+        assert_eq!(q.histogram(), BTreeMap::new());
+        assert_eq!(q.count_in_buckets_at_least(&0), 0);
+        // starting over from empty, the old bounds are forgotten
+        q.my_enqueue(33, 33);
+        assert_eq!(
+            q.occupied_bounds(),
+            (&Coarse::coarse_grain(&33), &Coarse::coarse_grain(&33))
+        );
+    }
+
+    /// first in first out but keeping the priorities
+    #[allow(dead_code)]
+    struct KeepsPriority(std::collections::VecDeque<(usize, usize)>);
+
+    impl AbstractPriorityQueue<usize, usize> for KeepsPriority {
+        fn empty_copy(&self) -> Self {
+            Self(std::collections::VecDeque::new())
+        }
+
+        fn my_peek(&self) -> Option<(&usize, &usize)> {
+            self.0.front().map(|(z, w)| (z, w))
+        }
+
+        fn my_enqueue(&mut self, new_obj: usize, new_obj_priority: usize) {
+            self.0.push_back((new_obj, new_obj_priority));
+        }
+
+        fn enqueue_batch(
+            &mut self,
+            new_batch: impl IntoIterator<Item = usize>,
+            new_batch_priority: usize,
+        ) {
+            self.0
+                .extend(new_batch.into_iter().map(|z| (z, new_batch_priority)));
+        }
+
+        fn my_dequeue(&mut self) -> Option<(usize, usize)> {
+            self.0.pop_front()
+        }
+
+        fn dequeue_batch(
+            &mut self,
+            around_how_many: usize,
+            _hard_limit: usize,
+        ) -> Vec<(usize, usize)> {
+            let around_how_many = around_how_many.min(self.0.len());
+            self.0.drain(0..around_how_many).collect()
+        }
+
+        fn my_len(&self) -> usize {
+            self.0.len()
+        }
+
+        fn is_empty(&self) -> bool {
+            self.0.is_empty()
+        }
+    }
+
+    impl GivesBackPriorities<usize, usize> for KeepsPriority {}
+
+    #[test]
+    fn runtime_rebucket() {
+        use super::BucketQueue;
+        use std::collections::{BTreeSet, HashMap};
+
+        let divide_by = |width: usize| move |p: &usize| *p / width;
+        let mut q = BucketQueue::<
+            _,
+            _,
+            usize,
+            KeepsPriority,
+            HashMap<usize, KeepsPriority>,
+            BTreeSet<usize>,
+            _,
+        >::with_coarse_grainer(
+            0,
+            0,
+            &KeepsPriority(std::collections::VecDeque::new()),
+            divide_by(100),
+        );
+        let mut step = 5usize;
+        for idx in 0..300 {
+            step = step.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            q.my_enqueue(idx, (step >> 40) % 1000);
+        }
+        assert_eq!(q.histogram().len(), 10);
+        q.rebucket(divide_by(10));
+        assert_eq!(q.my_len(), 300);
+        let histogram = q.histogram();
+        assert!(histogram.len() > 10);
+        assert_eq!(
+            q.occupied_bounds(),
+            (
+                histogram.keys().next().expect("nonempty"),
+                histogram.keys().last().expect("nonempty")
+            )
+        );
+        let all_out = q.drain_all();
+        assert_eq!(all_out.len(), 300);
+        // the buckets are in order and inside each one the items are in the order they came in
+        assert!(all_out.windows(2).all(|w| {
+            let (bucket_0, bucket_1) = (w[0].1 / 10, w[1].1 / 10);
+            bucket_0 > bucket_1 || (bucket_0 == bucket_1 && w[0].0 < w[1].0)
+        }));
+    }
+
+    #[test]
+    fn bucket_pool() {
+        use super::BucketQueue;
+        use crate::no_priority_queue::NoPriorityQueue;
+        use std::collections::HashMap;
+
+        type Bucket = NoPriorityQueue<usize, usize>;
+        let mut q =
+            BucketQueue::<_, _, usize, Bucket, HashMap<usize, Bucket>>::new(0, 0, &Bucket::new());
+        q.enqueue_batch(0..10, 1);
+        q.enqueue_batch(10..20, 2);
+        q.enqueue_batch(20..30, 3);
+        q.drain_all();
+        // turned off by default
+        assert_eq!(q.bucket_pool_len(), 0);
+        q.change_bucket_pool_limit(2);
+        q.enqueue_batch(0..10, 1);
+        q.enqueue_batch(10..20, 2);
+        q.enqueue_batch(20..30, 3);
+        assert_eq!(q.dequeue_batch(25, 25).len(), 25);
+        assert_eq!(q.bucket_pool_len(), 2);
+        q.my_enqueue(30, 4);
+        assert_eq!(q.bucket_pool_len(), 1);
+        assert_eq!(q.my_len(), 6);
+        q.drain_all();
+        assert_eq!(q.bucket_pool_len(), 2);
+        q.change_bucket_pool_limit(1);
+        assert_eq!(q.bucket_pool_len(), 1);
+        // reused buckets work like new
+        q.enqueue_batch(40..45, 5);
+        q.enqueue_batch(50..55, 0);
+        let all_out: Vec<_> = q.drain_all().into_iter().map(|(z, _)| z).collect();
+        assert_eq!(all_out, (40..45).chain(50..55).collect::<Vec<_>>());
+    }
+}
@@ -1,7 +1,7 @@
 use priority_queue::PriorityQueue;
 use std::hash::Hash;
 
-use crate::my_priority_queue::AbstractPriorityQueue;
+use crate::my_priority_queue::{AbstractPriorityQueue, GivesBackPriorities};
 
 /// the ordinary priority queue
 /// of course implements the trait `AbstractPriorityQueue`
@@ -60,6 +60,13 @@ where
     }
 }
 
+impl<T, P> GivesBackPriorities<T, P> for PriorityQueue<T, P>
+where
+    T: Eq + Hash,
+    P: Ord + Clone,
+{
+}
+
 mod test {
 
     #[test]
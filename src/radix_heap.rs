@@ -0,0 +1,349 @@
+use std::{cmp::Reverse, fmt};
+
+use crate::my_priority_queue::{AbstractPriorityQueue, GivesBackPriorities};
+
+/// an order preserving map of the priorities into `u64`
+/// so that a `RadixHeap` can bucket them by which bits they share
+pub trait RadixKey {
+    fn radix_key(&self) -> u64;
+}
+
+macro_rules! unsigned_radix_key {
+    ($($t:ty),*) => {
+        $(
+            impl RadixKey for $t {
+                fn radix_key(&self) -> u64 {
+                    *self as u64
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! signed_radix_key {
+    ($($t:ty),*) => {
+        $(
+            impl RadixKey for $t {
+                /// flipping the sign bit puts the negatives below the nonnegatives
+                fn radix_key(&self) -> u64 {
+                    (*self as i64 as u64) ^ (1 << 63)
+                }
+            }
+        )*
+    };
+}
+
+unsigned_radix_key!(u8, u16, u32, u64, usize);
+signed_radix_key!(i8, i16, i32, i64, isize);
+
+/// for when smaller is more urgent like distances or times
+impl<K: RadixKey> RadixKey for Reverse<K> {
+    fn radix_key(&self) -> u64 {
+        !self.0.radix_key()
+    }
+}
+
+/// an item was enqueued with a higher priority than one already dequeued
+/// it is given back here
+pub struct NotMonotone<T, P> {
+    pub item: T,
+    pub priority: P,
+    pub last_dequeued: P,
+}
+
+impl<T, P: fmt::Debug> fmt::Debug for NotMonotone<T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NotMonotone")
+            .field("priority", &self.priority)
+            .field("last_dequeued", &self.last_dequeued)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T, P: fmt::Debug> fmt::Display for NotMonotone<T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "priority {:?} was enqueued after {:?} was already dequeued, but the priorities of a RadixHeap can only go down",
+            self.priority, self.last_dequeued
+        )
+    }
+}
+
+impl<T, P: fmt::Debug> std::error::Error for NotMonotone<T, P> {}
+
+const NUM_BUCKETS: usize = u64::BITS as usize + 1;
+
+/// a monotone priority queue, nothing enqueued can beat what was already dequeued
+/// as in shortest paths or event simulation (with `Reverse` priorities)
+/// bucket 0 has the priorities equal to the last one dequeued
+/// and bucket i those which first differ from it in bit i-1
+/// so the buckets grow in powers of two going away from the last one dequeued
+/// when bucket 0 runs out, the next bucket is redistributed around its best priority
+pub struct RadixHeap<T, P>
+where
+    P: Ord + Clone + RadixKey,
+{
+    buckets: Vec<Vec<(u64, T, P)>>,
+    last_key: u64,
+    last_dequeued: Option<P>,
+    total_len: usize,
+}
+
+impl<T, P> Default for RadixHeap<T, P>
+where
+    P: Ord + Clone + RadixKey,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, P> RadixHeap<T, P>
+where
+    P: Ord + Clone + RadixKey,
+{
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            buckets: (0..NUM_BUCKETS).map(|_| Vec::new()).collect(),
+            last_key: u64::MAX,
+            last_dequeued: None,
+            total_len: 0,
+        }
+    }
+
+    /// the priority of the last item dequeued
+    /// nothing better than this can be enqueued anymore
+    pub fn last_dequeued(&self) -> Option<&P> {
+        self.last_dequeued.as_ref()
+    }
+
+    /// which bucket a key goes in relative to the last one dequeued
+    fn which_bucket(&self, key: u64) -> usize {
+        (u64::BITS - (key ^ self.last_key).leading_zeros()) as usize
+    }
+
+    /// # Errors
+    /// if `new_obj_priority` is higher than the last priority dequeued
+    pub fn try_enqueue(
+        &mut self,
+        new_obj: T,
+        new_obj_priority: P,
+    ) -> Result<(), NotMonotone<T, P>> {
+        let key = new_obj_priority.radix_key();
+        if key > self.last_key {
+            return Err(NotMonotone {
+                item: new_obj,
+                priority: new_obj_priority,
+                last_dequeued: self
+                    .last_dequeued
+                    .clone()
+                    .expect("nothing is above the initial last key"),
+            });
+        }
+        let which_bucket = self.which_bucket(key);
+        self.buckets[which_bucket].push((key, new_obj, new_obj_priority));
+        self.total_len += 1;
+        Ok(())
+    }
+
+    /// the first nonempty bucket other than 0
+    /// and where in it is the item with the best priority
+    /// the last of them when there are ties
+    fn next_best(&self) -> Option<(usize, usize)> {
+        let (which_bucket, bucket) = self
+            .buckets
+            .iter()
+            .enumerate()
+            .skip(1)
+            .find(|(_, bucket)| !bucket.is_empty())?;
+        let (idx, _) = bucket
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, (key, _, _))| *key)
+            .expect("bucket is nonempty");
+        Some((which_bucket, idx))
+    }
+
+    /// bucket 0 is empty so the next best becomes the last key
+    /// and the bucket it was in gets redistributed into lower buckets
+    /// the next best ends up last in bucket 0 matching `my_peek`
+    fn redistribute(&mut self) -> Option<()> {
+        let (which_bucket, idx) = self.next_best()?;
+        self.last_key = self.buckets[which_bucket][idx].0;
+        for (key, z, w) in std::mem::take(&mut self.buckets[which_bucket]) {
+            let new_bucket = self.which_bucket(key);
+            self.buckets[new_bucket].push((key, z, w));
+        }
+        Some(())
+    }
+}
+
+impl<T, P> AbstractPriorityQueue<T, P> for RadixHeap<T, P>
+where
+    P: Ord + Clone + RadixKey,
+{
+    fn empty_copy(&self) -> Self {
+        Self::new()
+    }
+
+    fn my_peek(&self) -> Option<(&T, &P)> {
+        if let Some((_, z, w)) = self.buckets[0].last() {
+            return Some((z, w));
+        }
+        let (which_bucket, idx) = self.next_best()?;
+        let (_, z, w) = &self.buckets[which_bucket][idx];
+        Some((z, w))
+    }
+
+    /// # Panics
+    /// if `new_obj_priority` is higher than the last priority dequeued
+    /// use `try_enqueue` to get that back as an error instead
+    fn my_enqueue(&mut self, new_obj: T, new_obj_priority: P) {
+        let enqueued = self.try_enqueue(new_obj, new_obj_priority);
+        assert!(
+            enqueued.is_ok(),
+            "a priority was enqueued after a higher one was already dequeued, but the priorities of a RadixHeap can only go down"
+        );
+    }
+
+    fn enqueue_batch(&mut self, new_batch: impl IntoIterator<Item = T>, new_batch_priority: P) {
+        for z in new_batch {
+            self.my_enqueue(z, new_batch_priority.clone());
+        }
+    }
+
+    fn my_dequeue(&mut self) -> Option<(T, P)> {
+        if self.buckets[0].is_empty() {
+            self.redistribute()?;
+        }
+        let (_, z, w) = self.buckets[0].pop().expect("just made bucket 0 nonempty");
+        self.total_len -= 1;
+        self.last_dequeued = Some(w.clone());
+        Some((z, w))
+    }
+
+    fn dequeue_batch(&mut self, around_how_many: usize, hard_limit: usize) -> Vec<(T, P)> {
+        assert!(hard_limit >= around_how_many);
+        let mut to_return = Vec::with_capacity(around_how_many);
+        while to_return.len() < around_how_many {
+            if let Some(real_put_in) = self.my_dequeue() {
+                to_return.push(real_put_in);
+            } else {
+                break;
+            }
+        }
+        to_return
+    }
+
+    fn my_len(&self) -> usize {
+        self.total_len
+    }
+
+    fn is_empty(&self) -> bool {
+        self.total_len == 0
+    }
+}
+
+impl<T, P> GivesBackPriorities<T, P> for RadixHeap<T, P> where P: Ord + Clone + RadixKey {}
+
+mod test {
+
+    #[test]
+    fn nothing_in_out() {
+        use super::RadixHeap;
+        use crate::my_priority_queue::AbstractPriorityQueue;
+
+        let mut q = RadixHeap::<u8, u32>::new();
+        assert_eq!(q.my_len(), 0);
+        assert!(q.is_empty());
+        assert!(q.my_peek().is_none());
+        assert!(q.my_dequeue().is_none());
+        assert!(q.dequeue_batch(10, 10).is_empty());
+        assert!(q.last_dequeued().is_none());
+    }
+
+    #[test]
+    fn shortest_paths() {
+        use super::RadixHeap;
+        use crate::my_priority_queue::AbstractPriorityQueue;
+        use std::cmp::Reverse;
+
+        // a grid where going right costs 3 and going down costs 5
+        let (width, height) = (20usize, 15usize);
+        let mut distances = vec![u32::MAX; width * height];
+        let mut q = RadixHeap::<usize, Reverse<u32>>::new();
+        q.my_enqueue(0, Reverse(0));
+        let mut last_distance = 0;
+        while let Some((peeked, _)) = q.my_peek().map(|(z, w)| (*z, *w)) {
+            let (here, Reverse(distance)) = q.my_dequeue().expect("just peeked");
+            assert_eq!(here, peeked);
+            assert!(distance >= last_distance);
+            last_distance = distance;
+            if distances[here] <= distance {
+                continue;
+            }
+            distances[here] = distance;
+            let (x, y) = (here % width, here / width);
+            if x + 1 < width {
+                q.my_enqueue(here + 1, Reverse(distance + 3));
+            }
+            if y + 1 < height {
+                q.my_enqueue(here + width, Reverse(distance + 5));
+            }
+        }
+        for (here, distance) in distances.into_iter().enumerate() {
+            let (x, y) = (here % width, here / width);
+            assert_eq!(distance as usize, 3 * x + 5 * y);
+        }
+    }
+
+    #[test]
+    fn same_order_as_sorting() {
+        use super::RadixHeap;
+        use crate::my_priority_queue::AbstractPriorityQueue;
+
+        let mut q = RadixHeap::<usize, i64>::new();
+        let mut all_in = Vec::new();
+        let mut all_out = Vec::new();
+        let mut step = 11u64;
+        let mut floor = 1_000i64;
+        for round in 0..200 {
+            for _ in 0..3 {
+                step = step.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+                let priority = floor - ((step >> 40) % 64) as i64;
+                q.my_enqueue(round, priority);
+                all_in.push(priority);
+            }
+            let out = q.dequeue_batch(2, 2);
+            floor = out.last().map_or(floor, |(_, w)| *w);
+            all_out.extend(out.into_iter().map(|(_, w)| w));
+        }
+        assert_eq!(q.my_len(), 200);
+        all_out.extend(q.drain_all().into_iter().map(|(_, w)| w));
+        assert!(all_out.windows(2).all(|w| w[0] >= w[1]));
+        all_in.sort_unstable();
+        all_out.reverse();
+        assert_eq!(all_in, all_out);
+    }
+
+    #[test]
+    fn not_monotone() {
+        use super::RadixHeap;
+        use crate::my_priority_queue::AbstractPriorityQueue;
+
+        let mut q = RadixHeap::<&str, i32>::new();
+        q.my_enqueue("a", 5);
+        q.my_enqueue("b", -3);
+        assert_eq!(q.my_dequeue(), Some(("a", 5)));
+        assert!(q.try_enqueue("c", 5).is_ok());
+        let e = q.try_enqueue("d", 6).unwrap_err();
+        assert_eq!((e.item, e.priority, e.last_dequeued), ("d", 6, 5));
+        assert_eq!(
+            e.to_string(),
+            "priority 6 was enqueued after 5 was already dequeued, but the priorities of a RadixHeap can only go down"
+        );
+        assert_eq!(q.drain_all(), vec![("c", 5), ("b", -3)]);
+    }
+}
@@ -0,0 +1,342 @@
+use priority_queue::PriorityQueue;
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+};
+
+use crate::friend_level::{default_combiner, PriorityCombiner};
+use crate::israeli_priority::Friendly;
+use crate::my_priority_queue::{AbstractPriorityQueue, GivesBackPriorities};
+
+/// how friend groups take turns in a `RoundRobinIsraeli`
+#[derive(Clone)]
+pub enum RoundRobinTurns<P> {
+    /// the groups are still ordered by priority,
+    /// but a group only gets `quantum` items in per turn
+    /// and then goes behind the other groups with the same priority
+    KeepPriority { quantum: usize },
+    /// the groups take turns in the order they got in line regardless of priority
+    /// and on its turn a group gets `weight` of its priority many items in (but at least 1)
+    /// this is deficit round robin where every item costs the same
+    Weighted { weight: fn(&P) -> usize },
+}
+
+/// the groups waiting for their next turn
+enum WaitingLine<H, P>
+where
+    H: Hash + Eq,
+    P: Ord,
+{
+    ByPriority(PriorityQueue<H, (P, Reverse<usize>)>),
+    Ring(VecDeque<H>),
+}
+
+/// Like the `IsraeliPriority`, friends with the same shibboleth are grouped together.
+/// But rather than a group going in entirely once it gets to the front,
+/// it only gets a limited number of items in per turn
+/// before the others get their turn.
+/// This way one busy group can not monopolize the queue.
+pub struct RoundRobinIsraeli<T, P, H>
+where
+    T: Friendly<H>,
+    P: Ord + Clone,
+    H: Eq + Hash + Clone,
+{
+    waiting_line: WaitingLine<H, P>,
+    shibboleth_to_friends: HashMap<H, (VecDeque<T>, P)>,
+    current_turn: Option<(H, usize)>,
+    turns: RoundRobinTurns<P>,
+    times_in_line: usize,
+    total_len: usize,
+    priority_combiner: PriorityCombiner<P>,
+}
+
+impl<T, P, H> RoundRobinIsraeli<T, P, H>
+where
+    T: Friendly<H>,
+    P: Ord + Clone,
+    H: Eq + Hash + Clone,
+{
+    /// setup with capacity for specified number of distinct friend groups
+    /// the way priorities of the portion already in line and a new friend joining
+    /// that friend group is the default which just picks the bigger of the two
+    /// # Panics
+    /// if `turns` gives a quantum of 0
+    #[must_use]
+    pub fn with_capacity(capacity: usize, turns: RoundRobinTurns<P>) -> Self {
+        let waiting_line = match turns {
+            RoundRobinTurns::KeepPriority { quantum } => {
+                assert!(quantum > 0, "every turn lets at least one in");
+                WaitingLine::ByPriority(PriorityQueue::with_capacity(capacity))
+            }
+            RoundRobinTurns::Weighted { .. } => {
+                WaitingLine::Ring(VecDeque::with_capacity(capacity))
+            }
+        };
+        Self {
+            waiting_line,
+            shibboleth_to_friends: HashMap::with_capacity(capacity),
+            current_turn: None,
+            turns,
+            times_in_line: 0,
+            total_len: 0,
+            priority_combiner: default_combiner,
+        }
+    }
+
+    /// provide a different way for priorities to combine
+    /// when a portion is already in line and a new friend joins
+    /// that friend group
+    pub fn change_combiner(&mut self, new_combiner: PriorityCombiner<P>) {
+        self.priority_combiner = new_combiner;
+    }
+
+    /// put the group with this shibboleth at the back of the line
+    fn get_in_line(&mut self, the_shibboleth: H, group_priority: P) {
+        match &mut self.waiting_line {
+            WaitingLine::ByPriority(underlying) => {
+                underlying.push(
+                    the_shibboleth,
+                    (group_priority, Reverse(self.times_in_line)),
+                );
+            }
+            WaitingLine::Ring(underlying) => {
+                underlying.push_back(the_shibboleth);
+            }
+        }
+        self.times_in_line += 1;
+    }
+
+    /// the shibboleth of the group whose turn is next
+    fn next_in_line(&self) -> Option<&H> {
+        match &self.waiting_line {
+            WaitingLine::ByPriority(underlying) => underlying.peek().map(|(h, _)| h),
+            WaitingLine::Ring(underlying) => underlying.front(),
+        }
+    }
+
+    /// the next group in line gets their turn
+    fn start_next_turn(&mut self) -> Option<()> {
+        let next_shibboleth = match &mut self.waiting_line {
+            WaitingLine::ByPriority(underlying) => underlying.pop().map(|(h, _)| h),
+            WaitingLine::Ring(underlying) => underlying.pop_front(),
+        }?;
+        let allowance = match &self.turns {
+            RoundRobinTurns::KeepPriority { quantum } => *quantum,
+            RoundRobinTurns::Weighted { weight } => {
+                let (_, group_priority) = self
+                    .shibboleth_to_friends
+                    .get(&next_shibboleth)
+                    .expect("all shibboleths in line have corresponding items");
+                weight(group_priority).max(1)
+            }
+        };
+        self.current_turn = Some((next_shibboleth, allowance));
+        Some(())
+    }
+}
+
+impl<T, P, H> AbstractPriorityQueue<T, P> for RoundRobinIsraeli<T, P, H>
+where
+    T: Friendly<H>,
+    P: Ord + Clone,
+    H: Hash + Eq + Clone,
+{
+    fn empty_copy(&self) -> Self {
+        let mut to_return =
+            Self::with_capacity(self.shibboleth_to_friends.len(), self.turns.clone());
+        to_return.change_combiner(self.priority_combiner);
+        to_return
+    }
+
+    fn my_peek(&self) -> Option<(&T, &P)> {
+        let next_shibboleth = match &self.current_turn {
+            Some((h, _)) => Some(h),
+            None => self.next_in_line(),
+        }?;
+        let (friend_grp, group_priority) = self
+            .shibboleth_to_friends
+            .get(next_shibboleth)
+            .expect("all shibboleths in line have corresponding items");
+        friend_grp.front().map(|z| (z, group_priority))
+    }
+
+    fn my_enqueue(&mut self, new_obj: T, new_obj_priority: P) {
+        let my_shibboleth = new_obj.friendship_shibboleth();
+        self.total_len += 1;
+        if let Some((friend_grp, old_priority)) = self.shibboleth_to_friends.get_mut(&my_shibboleth)
+        {
+            // found friends, either waiting for their turn or in the middle of it
+            friend_grp.push_back(new_obj);
+            let (priority_changed, new_priority) =
+                (self.priority_combiner)(old_priority, &new_obj_priority);
+            if priority_changed {
+                old_priority.clone_from(&new_priority);
+                if let WaitingLine::ByPriority(underlying) = &mut self.waiting_line {
+                    // keeping their place among others with the same priority
+                    let _overwritten = underlying.change_priority_by(&my_shibboleth, |(p, _)| {
+                        *p = new_priority;
+                    });
+                }
+            }
+        } else {
+            // no friends, so a new group gets in line
+            self.shibboleth_to_friends.insert(
+                my_shibboleth.clone(),
+                (VecDeque::from([new_obj]), new_obj_priority.clone()),
+            );
+            self.get_in_line(my_shibboleth, new_obj_priority);
+        }
+    }
+
+    fn enqueue_batch(&mut self, new_batch: impl IntoIterator<Item = T>, new_batch_priority: P) {
+        for z in new_batch {
+            self.my_enqueue(z, new_batch_priority.clone());
+        }
+    }
+
+    fn my_dequeue(&mut self) -> Option<(T, P)> {
+        if self.current_turn.is_none() {
+            self.start_next_turn()?;
+        }
+        let (cur_shibboleth, allowance) = self.current_turn.take().expect("Already filled in");
+        let (friend_grp, group_priority) = self
+            .shibboleth_to_friends
+            .get_mut(&cur_shibboleth)
+            .expect("the group whose turn it is has corresponding items");
+        let ret_val = friend_grp
+            .pop_front()
+            .map(|z| (z, group_priority.clone()))
+            .expect("empty groups are never kept around");
+        self.total_len -= 1;
+        if friend_grp.is_empty() {
+            self.shibboleth_to_friends.remove(&cur_shibboleth);
+        } else if allowance > 1 {
+            self.current_turn = Some((cur_shibboleth, allowance - 1));
+        } else {
+            // their turn is over, but some of them are left so back in line
+            let group_priority = group_priority.clone();
+            self.get_in_line(cur_shibboleth, group_priority);
+        }
+        Some(ret_val)
+    }
+
+    fn dequeue_batch(&mut self, around_how_many: usize, hard_limit: usize) -> Vec<(T, P)> {
+        assert!(hard_limit >= around_how_many);
+        let mut to_return = Vec::with_capacity(around_how_many);
+        for _ in 0..around_how_many {
+            let put_in = self.my_dequeue();
+            if let Some(real_put_in) = put_in {
+                to_return.push(real_put_in);
+            } else {
+                break;
+            }
+        }
+        to_return
+    }
+
+    fn my_len(&self) -> usize {
+        self.total_len
+    }
+
+    fn is_empty(&self) -> bool {
+        self.total_len == 0
+    }
+}
+
+/// the priority of the group they were in
+impl<T, P, H> GivesBackPriorities<T, P> for RoundRobinIsraeli<T, P, H>
+where
+    T: Friendly<H>,
+    P: Ord + Clone,
+    H: Hash + Eq + Clone,
+{
+}
+
+mod test {
+    use crate::Friendly;
+
+    const MY_U8_FREINDLINESS: u8 = 5;
+
+    #[allow(dead_code)]
+    #[derive(PartialEq, Eq, Debug)]
+    #[repr(transparent)]
+    struct MyU8(u8);
+    impl Friendly<u8> for MyU8 {
+        fn friendship_shibboleth(&self) -> u8 {
+            self.0 % MY_U8_FREINDLINESS
+        }
+    }
+
+    #[test]
+    fn nothing_in_out() {
+        use super::{RoundRobinIsraeli, RoundRobinTurns};
+        use crate::my_priority_queue::AbstractPriorityQueue;
+
+        let mut q = RoundRobinIsraeli::<MyU8, u8, u8>::with_capacity(
+            2,
+            RoundRobinTurns::KeepPriority { quantum: 2 },
+        );
+        assert_eq!(q.my_len(), 0);
+        assert!(q.is_empty());
+        assert!(q.my_peek().is_none());
+        assert!(q.my_dequeue().is_none());
+        assert!(q.dequeue_batch(10, 10).is_empty());
+    }
+
+    #[test]
+    fn keep_priority_quantum() {
+        use super::{RoundRobinIsraeli, RoundRobinTurns};
+        use crate::my_priority_queue::AbstractPriorityQueue;
+
+        let mut q = RoundRobinIsraeli::<MyU8, u8, u8>::with_capacity(
+            4,
+            RoundRobinTurns::KeepPriority { quantum: 2 },
+        );
+        q.enqueue_batch([0, 5, 10, 15, 20].map(MyU8), 5);
+        q.enqueue_batch([1, 6, 11].map(MyU8), 5);
+        q.my_enqueue(MyU8(2), 1);
+        assert_eq!(q.my_len(), 9);
+        assert_eq!(q.my_peek(), Some((&MyU8(0), &5)));
+        let all_out: Vec<_> = q.drain_all().into_iter().map(|(z, _)| z.0).collect();
+        assert_eq!(all_out, vec![0, 5, 1, 6, 10, 15, 11, 20, 2]);
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn weighted_turns() {
+        use super::{RoundRobinIsraeli, RoundRobinTurns};
+        use crate::my_priority_queue::AbstractPriorityQueue;
+
+        let mut q = RoundRobinIsraeli::<MyU8, u8, u8>::with_capacity(
+            4,
+            RoundRobinTurns::Weighted {
+                weight: |p| (*p).into(),
+            },
+        );
+        q.enqueue_batch([1, 6, 11, 16].map(MyU8), 1);
+        q.enqueue_batch([0, 5, 10, 15, 20].map(MyU8), 2);
+        q.my_enqueue(MyU8(2), 0);
+        let all_out: Vec<_> = q
+            .dequeue_batch(10, 10)
+            .into_iter()
+            .map(|(z, _)| z.0)
+            .collect();
+        assert_eq!(all_out, vec![1, 0, 5, 2, 6, 10, 15, 11, 20, 16]);
+    }
+
+    #[test]
+    fn inside_reordered() {
+        use super::{RoundRobinIsraeli, RoundRobinTurns};
+        use crate::Reorderable;
+
+        let q = RoundRobinIsraeli::<MyU8, u8, u8>::with_capacity(
+            4,
+            RoundRobinTurns::KeepPriority { quantum: 1 },
+        );
+        let it = [0, 5, 10, 1, 6, 11].map(|z| Ok((MyU8(z), 3))).into_iter();
+        let all_out: Vec<_> = it.reorder(q, 6).map(|(z, _)| z.0).collect();
+        assert_eq!(all_out, vec![0, 1, 5, 6, 10, 11]);
+    }
+}
@@ -0,0 +1,347 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    hash::Hash,
+    marker::PhantomData,
+};
+
+use crate::friend_level::PriorityCombiner;
+use crate::israeli_of_queues::IsraeliOfQueues;
+use crate::israeli_priority::{FoyerPreemption, Friendly, IsraeliPriority};
+use crate::my_priority_queue::AbstractPriorityQueue;
+use crate::nested_queue::{
+    BucketFactory, BucketQueue, ByCoarseGrainedPriority, CoarseGrainedPriority, CoarseGrainer,
+    IndexInto, OccupancyIndex,
+};
+
+/// builds an `IsraeliPriority` with all the settings at once
+pub struct IsraeliPriorityBuilder<T, P, H> {
+    capacity: usize,
+    priority_combiner: Option<PriorityCombiner<P>>,
+    foyer_preemption: Option<FoyerPreemption<P>>,
+    junk: PhantomData<(T, H)>,
+}
+
+impl<T, P, H> Default for IsraeliPriorityBuilder<T, P, H>
+where
+    T: Friendly<H>,
+    P: Ord + Clone,
+    H: Eq + Hash + Clone,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, P, H> IsraeliPriorityBuilder<T, P, H>
+where
+    T: Friendly<H>,
+    P: Ord + Clone,
+    H: Eq + Hash + Clone,
+{
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            capacity: 0,
+            priority_combiner: None,
+            foyer_preemption: None,
+            junk: PhantomData,
+        }
+    }
+
+    /// how many distinct friend groups to have room for
+    #[must_use]
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// see `IsraeliPriority::change_combiner`
+    #[must_use]
+    pub fn combiner(mut self, priority_combiner: PriorityCombiner<P>) -> Self {
+        self.priority_combiner = Some(priority_combiner);
+        self
+    }
+
+    /// see `IsraeliPriority::change_foyer_preemption`
+    #[must_use]
+    pub fn foyer_preemption(mut self, foyer_preemption: FoyerPreemption<P>) -> Self {
+        self.foyer_preemption = Some(foyer_preemption);
+        self
+    }
+
+    #[must_use]
+    pub fn build(self) -> IsraeliPriority<T, P, H> {
+        let mut to_return = IsraeliPriority::with_capacity(self.capacity);
+        if let Some(priority_combiner) = self.priority_combiner {
+            to_return.change_combiner(priority_combiner);
+        }
+        if let Some(foyer_preemption) = self.foyer_preemption {
+            to_return.change_foyer_preemption(foyer_preemption);
+        }
+        to_return
+    }
+
+    /// an `IsraeliOfQueues` with these settings instead
+    /// where each friend group is an empty copy of `group_template`
+    /// which can itself come from a builder, like for an Israeli queue of buckets
+    #[must_use]
+    pub fn build_of_queues<Q: AbstractPriorityQueue<T, P>>(
+        self,
+        group_template: &Q,
+    ) -> IsraeliOfQueues<T, P, H, Q> {
+        let mut to_return = IsraeliOfQueues::with_capacity(self.capacity, group_template);
+        if let Some(priority_combiner) = self.priority_combiner {
+            to_return.change_combiner(priority_combiner);
+        }
+        if let Some(foyer_preemption) = self.foyer_preemption {
+            to_return.change_foyer_preemption(foyer_preemption);
+        }
+        to_return
+    }
+}
+
+/// builds a `BucketQueue` with all the settings at once
+/// `Storer` and `Occupied` are a `HashMap` and a `BTreeSet` unless changed with `storer` and `occupancy`
+/// the buckets are copies of `bucket_template`
+/// which can itself come from a builder, so the disciplines compose
+/// like buckets of Israeli queues or buckets of buckets
+/// and `IsraeliPriorityBuilder::build_of_queues` goes the other way
+pub struct BucketQueueBuilder<
+    T,
+    P,
+    C,
+    Q,
+    Storer = HashMap<C, Q>,
+    Occupied = BTreeSet<C>,
+    Grainer = ByCoarseGrainedPriority,
+> {
+    bucket_template: Q,
+    bounds: Option<(C, C)>,
+    coarse_grainer: Grainer,
+    bucket_weights: Option<fn(&C) -> usize>,
+    bucket_factory: Option<BucketFactory<C, Q>>,
+    bucket_pool_limit: usize,
+    junk: PhantomData<(T, P, Storer, Occupied)>,
+}
+
+impl<T, P, C, Q> BucketQueueBuilder<T, P, C, Q>
+where
+    C: CoarseGrainedPriority<P> + Hash + Ord + Clone,
+    P: Ord,
+    Q: AbstractPriorityQueue<T, P>,
+{
+    /// coarse grained by `CoarseGrainedPriority` for C
+    pub fn new(bucket_template: Q) -> Self {
+        Self::with_coarse_grainer(bucket_template, ByCoarseGrainedPriority)
+    }
+}
+
+impl<T, P, C, Q, Grainer> BucketQueueBuilder<T, P, C, Q, HashMap<C, Q>, BTreeSet<C>, Grainer>
+where
+    C: Hash + Ord + Clone,
+    P: Ord,
+    Q: AbstractPriorityQueue<T, P>,
+    Grainer: CoarseGrainer<P, C>,
+{
+    /// coarse grained by `coarse_grainer` chosen at runtime
+    pub fn with_coarse_grainer(bucket_template: Q, coarse_grainer: Grainer) -> Self {
+        Self {
+            bucket_template,
+            bounds: None,
+            coarse_grainer,
+            bucket_weights: None,
+            bucket_factory: None,
+            bucket_pool_limit: 0,
+            junk: PhantomData,
+        }
+    }
+}
+
+impl<T, P, C, Q, Storer, Occupied, Grainer>
+    BucketQueueBuilder<T, P, C, Q, Storer, Occupied, Grainer>
+where
+    C: Hash + Ord + Clone,
+    P: Ord,
+    Q: AbstractPriorityQueue<T, P>,
+    Storer: IndexInto<C, Q>,
+    Occupied: OccupancyIndex<C>,
+    Grainer: CoarseGrainer<P, C>,
+{
+    /// store the buckets in `NewStorer` instead
+    #[must_use]
+    pub fn storer<NewStorer: IndexInto<C, Q>>(
+        self,
+    ) -> BucketQueueBuilder<T, P, C, Q, NewStorer, Occupied, Grainer> {
+        BucketQueueBuilder {
+            bucket_template: self.bucket_template,
+            bounds: self.bounds,
+            coarse_grainer: self.coarse_grainer,
+            bucket_weights: self.bucket_weights,
+            bucket_factory: self.bucket_factory,
+            bucket_pool_limit: self.bucket_pool_limit,
+            junk: PhantomData,
+        }
+    }
+
+    /// track the occupied buckets with `NewOccupied` instead
+    #[must_use]
+    pub fn occupancy<NewOccupied: OccupancyIndex<C>>(
+        self,
+    ) -> BucketQueueBuilder<T, P, C, Q, Storer, NewOccupied, Grainer> {
+        BucketQueueBuilder {
+            bucket_template: self.bucket_template,
+            bounds: self.bounds,
+            coarse_grainer: self.coarse_grainer,
+            bucket_weights: self.bucket_weights,
+            bucket_factory: self.bucket_factory,
+            bucket_pool_limit: self.bucket_pool_limit,
+            junk: PhantomData,
+        }
+    }
+
+    /// the coarse grained priorities expected, see `BucketQueue::new`
+    /// otherwise both are the default
+    #[must_use]
+    pub fn bounds(mut self, lower_bound: C, upper_bound: C) -> Self {
+        self.bounds = Some((lower_bound, upper_bound));
+        self
+    }
+
+    /// see `BucketQueue::change_bucket_weights`
+    #[must_use]
+    pub fn bucket_weights(mut self, weight: fn(&C) -> usize) -> Self {
+        self.bucket_weights = Some(weight);
+        self
+    }
+
+    /// see `BucketQueue::change_bucket_factory`
+    #[must_use]
+    pub fn bucket_factory(mut self, bucket_factory: BucketFactory<C, Q>) -> Self {
+        self.bucket_factory = Some(bucket_factory);
+        self
+    }
+
+    /// see `BucketQueue::change_bucket_pool_limit`
+    #[must_use]
+    pub fn bucket_pool_limit(mut self, bucket_pool_limit: usize) -> Self {
+        self.bucket_pool_limit = bucket_pool_limit;
+        self
+    }
+
+    pub fn build(self) -> BucketQueue<T, P, C, Q, Storer, Occupied, Grainer>
+    where
+        C: Default,
+    {
+        let (lower_bound, upper_bound) = self.bounds.unwrap_or_default();
+        let mut to_return = BucketQueue::with_coarse_grainer(
+            lower_bound,
+            upper_bound,
+            &self.bucket_template,
+            self.coarse_grainer,
+        );
+        to_return.change_bucket_weights(self.bucket_weights);
+        to_return.change_bucket_factory(self.bucket_factory);
+        to_return.change_bucket_pool_limit(self.bucket_pool_limit);
+        to_return
+    }
+}
+
+mod test {
+    use crate::Friendly;
+
+    const MY_U8_FREINDLINESS: u8 = 5;
+
+    #[allow(dead_code)]
+    #[derive(PartialEq, Eq, Debug, Hash)]
+    #[repr(transparent)]
+    struct MyU8(u8);
+    impl Friendly<u8> for MyU8 {
+        fn friendship_shibboleth(&self) -> u8 {
+            self.0 % MY_U8_FREINDLINESS
+        }
+    }
+
+    #[test]
+    fn buckets_of_israeli() {
+        use super::{BucketQueueBuilder, IsraeliPriorityBuilder};
+        use crate::coarse_grainers::DivideBy;
+        use crate::my_priority_queue::AbstractPriorityQueue;
+        use crate::{BucketQueue, CoarseGrainedPriority, HierarchicalBitmap, IsraeliPriority};
+        use std::collections::HashMap;
+
+        let mut built = BucketQueueBuilder::<MyU8, u8, DivideBy<u8, 10>, _>::new(
+            IsraeliPriorityBuilder::<MyU8, u8, u8>::new()
+                .capacity(4)
+                .combiner(|p1, p2| (false, *p1.max(p2)))
+                .build(),
+        )
+        .bounds(DivideBy(0), DivideBy(25))
+        .bucket_pool_limit(2)
+        .build();
+
+        type Bucket = IsraeliPriority<MyU8, u8, u8>;
+        let mut template = Bucket::with_capacity(4);
+        template.change_combiner(|p1, p2| (false, *p1.max(p2)));
+        let mut by_hand = BucketQueue::<
+            MyU8,
+            u8,
+            DivideBy<u8, 10>,
+            Bucket,
+            HashMap<DivideBy<u8, 10>, Bucket>,
+        >::new(DivideBy(0), DivideBy(25), &template);
+        by_hand.change_bucket_pool_limit(2);
+
+        for q in [&mut built, &mut by_hand] {
+            for z in [3, 13, 8, 21, 26, 11, 16] {
+                q.my_enqueue(MyU8(z), z * 2);
+            }
+        }
+        assert_eq!(built.histogram(), by_hand.histogram());
+        assert_eq!(built.drain_all(), by_hand.drain_all());
+        assert_eq!(built.bucket_pool_len(), 2);
+
+        // buckets of buckets with a different occupancy index
+        let mut nested =
+            BucketQueueBuilder::<usize, usize, usize, _, _, _, _>::with_coarse_grainer(
+                BucketQueueBuilder::<usize, usize, DivideBy<usize, 10>, _>::new(
+                    crate::NoPriorityQueue::new(),
+                )
+                .build(),
+                |p: &usize| *p / 100,
+            )
+            .occupancy::<HierarchicalBitmap>()
+            .build();
+        for z in [5, 150, 99, 101, 342] {
+            nested.my_enqueue(z, z);
+        }
+        let all_out: Vec<_> = nested.drain_all().into_iter().map(|(z, _)| z).collect();
+        assert_eq!(all_out, vec![342, 150, 101, 99, 5]);
+        assert_eq!(DivideBy::<usize, 10>::coarse_grain(&99), DivideBy(9));
+    }
+
+    #[test]
+    fn israeli_of_buckets() {
+        use super::{BucketQueueBuilder, IsraeliPriorityBuilder};
+        use crate::coarse_grainers::DivideBy;
+        use crate::my_priority_queue::AbstractPriorityQueue;
+        use crate::FoyerPreemption;
+        use priority_queue::PriorityQueue;
+
+        let mut q = IsraeliPriorityBuilder::<MyU8, u8, u8>::new()
+            .combiner(|p1, _| (false, *p1))
+            .foyer_preemption(FoyerPreemption::AtLeast(50))
+            .build_of_queues(
+                &BucketQueueBuilder::<MyU8, u8, DivideBy<u8, 10>, _>::new(PriorityQueue::new())
+                    .build(),
+            );
+        for z in [3, 13, 8, 21, 26, 11, 16] {
+            q.my_enqueue(MyU8(z), z * 2);
+        }
+        // the groups keep the priority they started with
+        // and inside each group the buckets order the items
+        assert_eq!(q.group_priority(&1), Some(&42));
+        assert_eq!(q.group_priority(&3), Some(&6));
+        let all_out: Vec<_> = q.drain_all().into_iter().map(|(z, _)| z.0).collect();
+        assert_eq!(all_out, vec![26, 21, 16, 11, 13, 8, 3]);
+    }
+}
@@ -0,0 +1,530 @@
+use priority_queue::PriorityQueue;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    hash::Hash,
+    num::{NonZeroU64, NonZeroUsize},
+};
+
+use crate::boxed_queue::BoxedQueue;
+use crate::friend_level::{default_combiner, PriorityCombiner};
+use crate::israeli_of_queues::IsraeliOfQueues;
+use crate::israeli_priority::{Friendly, IsraeliPriority};
+use crate::nested_queue::{BucketQueue, CoarseGrainer};
+use crate::no_priority_queue::NoPriorityQueue;
+use crate::radix_heap::{RadixHeap, RadixKey};
+use crate::round_robin::{RoundRobinIsraeli, RoundRobinTurns};
+
+/// how the priority of a friend group changes when a new friend joins
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CombinerConfig {
+    /// the bigger of the two
+    #[default]
+    Max,
+    /// the group keeps the priority it already had
+    KeepFirst,
+    /// the priority of the newest friend
+    Latest,
+}
+
+impl CombinerConfig {
+    fn combiner<P: Ord + Clone>(self) -> PriorityCombiner<P> {
+        match self {
+            Self::Max => default_combiner,
+            Self::KeepFirst => |p1, _| (false, p1.clone()),
+            Self::Latest => |_, p2| (true, p2.clone()),
+        }
+    }
+}
+
+/// how priorities are coarse grained into buckets
+/// these work on the `RadixKey` of the priority
+/// which for unsigned integers is just the value
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CoarseConfig {
+    /// every key is its own bucket
+    Identity,
+    /// this many consecutive keys per bucket
+    DivideBy(NonZeroU64),
+    /// keys by how many bits they need
+    Log2,
+    /// only the top this many bits of the key
+    TopBits(u32),
+}
+
+impl CoarseConfig {
+    /// the bucket for a priority with this `RadixKey`
+    fn coarse_grain_key(self, key: u64) -> u64 {
+        match self {
+            Self::Identity => key,
+            Self::DivideBy(width) => key / width.get(),
+            Self::Log2 => u64::from(u64::BITS - key.leading_zeros()),
+            Self::TopBits(how_many) => {
+                let shift = u64::BITS - how_many.min(u64::BITS);
+                key.checked_shr(shift).unwrap_or(0)
+            }
+        }
+    }
+}
+
+/// works on the `RadixKey` so every bucket is just one match away, without boxing a closure
+impl<P: RadixKey> CoarseGrainer<P, u64> for CoarseConfig {
+    fn coarse_grain(&self, p: &P) -> u64 {
+        self.coarse_grain_key(p.radix_key())
+    }
+}
+
+type PriorityMaker<T, P> = fn(usize) -> BoxedQueue<T, P>;
+type IsraeliMaker<T, P> = fn(usize, CombinerConfig, Option<&BoxedQueue<T, P>>) -> BoxedQueue<T, P>;
+type RoundRobinMaker<T, P> = fn(usize, NonZeroUsize, CombinerConfig) -> BoxedQueue<T, P>;
+
+/// which kinds of queue `QueueConfig::build` can make for these items and priorities
+/// every kind is turned on by the method that asks for what it needs
+/// so a configuration that only uses some kinds
+/// can be built for items and priorities that could not be put in the others
+pub struct ConfigSupport<T, P: Ord> {
+    fifo: Option<fn() -> BoxedQueue<T, P>>,
+    priority: Option<PriorityMaker<T, P>>,
+    israeli: Option<IsraeliMaker<T, P>>,
+    round_robin: Option<RoundRobinMaker<T, P>>,
+    radix: Option<fn() -> BoxedQueue<T, P>>,
+    radix_key: Option<fn(&P) -> u64>,
+}
+
+impl<T: 'static, P: Ord + Clone + 'static> Default for ConfigSupport<T, P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: 'static, P: Ord + Clone + 'static> ConfigSupport<T, P> {
+    /// none of them yet
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            fifo: None,
+            priority: None,
+            israeli: None,
+            round_robin: None,
+            radix: None,
+            radix_key: None,
+        }
+    }
+
+    /// `fifo`, which gives back the default priority
+    #[must_use]
+    pub fn default_priorities(mut self) -> Self
+    where
+        P: Default,
+    {
+        self.fifo = Some(|| BoxedQueue::new(NoPriorityQueue::new()));
+        self
+    }
+
+    /// `priority`, which hashes the items
+    #[must_use]
+    pub fn hashable_items(mut self) -> Self
+    where
+        T: Eq + Hash,
+    {
+        self.priority = Some(|capacity| BoxedQueue::new(PriorityQueue::with_capacity(capacity)));
+        self
+    }
+
+    /// `israeli` and `round_robin`, where the friendships are by shibboleths of type H
+    #[must_use]
+    pub fn friendly<H>(mut self) -> Self
+    where
+        T: Friendly<H>,
+        H: Eq + Hash + Clone + 'static,
+    {
+        self.israeli = Some(|capacity, combiner, inner| match inner {
+            None => {
+                let mut to_return = IsraeliPriority::<T, P, H>::with_capacity(capacity);
+                to_return.change_combiner(combiner.combiner());
+                BoxedQueue::new(to_return)
+            }
+            Some(inner) => {
+                let mut to_return =
+                    IsraeliOfQueues::<T, P, H, BoxedQueue<T, P>>::with_capacity(capacity, inner);
+                to_return.change_combiner(combiner.combiner());
+                BoxedQueue::new(to_return)
+            }
+        });
+        self.round_robin = Some(|capacity, quantum, combiner| {
+            let mut to_return = RoundRobinIsraeli::<T, P, H>::with_capacity(
+                capacity,
+                RoundRobinTurns::KeepPriority {
+                    quantum: quantum.get(),
+                },
+            );
+            to_return.change_combiner(combiner.combiner());
+            BoxedQueue::new(to_return)
+        });
+        self
+    }
+
+    /// `radix` and `bucket`, which go by the `RadixKey` of the priorities
+    #[must_use]
+    pub fn radix_keys(mut self) -> Self
+    where
+        P: RadixKey,
+    {
+        self.radix = Some(|| BoxedQueue::new(RadixHeap::new()));
+        self.radix_key = Some(P::radix_key);
+        self
+    }
+}
+
+/// the configuration has a kind of queue that its `ConfigSupport` did not turn on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unsupported {
+    pub kind: &'static str,
+    pub needs: &'static str,
+}
+
+impl fmt::Display for Unsupported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "a {} queue was configured but ConfigSupport::{} was not used",
+            self.kind, self.needs
+        )
+    }
+}
+
+impl std::error::Error for Unsupported {}
+
+/// which queue to build, for choosing the topology in a configuration file
+/// the `kind` key says which one, like
+/// ```toml
+/// kind = "bucket"
+/// coarse = { divide_by = 10 }
+///
+/// [inner]
+/// kind = "israeli"
+/// combiner = "keep_first"
+/// ```
+#[derive(Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum QueueConfig {
+    /// `NoPriorityQueue`
+    /// with braces so that `deny_unknown_fields` also applies to it
+    Fifo {},
+    /// the ordinary `PriorityQueue`
+    Priority {
+        #[serde(default)]
+        capacity: usize,
+    },
+    /// `IsraeliPriority`, or with an `inner` the `IsraeliOfQueues` where each friend group is one
+    Israeli {
+        #[serde(default)]
+        capacity: usize,
+        #[serde(default)]
+        combiner: CombinerConfig,
+        #[serde(default)]
+        inner: Option<Box<QueueConfig>>,
+    },
+    /// `RoundRobinIsraeli` where groups keep their priority and get `quantum` in per turn
+    RoundRobin {
+        #[serde(default)]
+        capacity: usize,
+        quantum: NonZeroUsize,
+        #[serde(default)]
+        combiner: CombinerConfig,
+    },
+    /// `RadixHeap`
+    Radix {},
+    /// `BucketQueue` where each bucket is an `inner`
+    /// `lower` and `upper` are bounds on the coarse grained priorities
+    Bucket {
+        coarse: CoarseConfig,
+        #[serde(default)]
+        lower: u64,
+        #[serde(default)]
+        upper: u64,
+        #[serde(default)]
+        pool_limit: usize,
+        inner: Box<QueueConfig>,
+    },
+}
+
+impl QueueConfig {
+    /// # Errors
+    /// if `config` is not TOML for a `QueueConfig`
+    pub fn from_toml(config: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(config)
+    }
+
+    /// the queue this describes
+    /// with the kinds of queue that `support` turned on
+    ///
+    /// # Errors
+    /// if the configuration uses a kind that `support` did not turn on
+    pub fn build<T, P>(
+        &self,
+        support: &ConfigSupport<T, P>,
+    ) -> Result<BoxedQueue<T, P>, Unsupported>
+    where
+        T: 'static,
+        P: Ord + Clone + 'static,
+    {
+        let unsupported = |kind, needs| Unsupported { kind, needs };
+        Ok(match self {
+            Self::Fifo {} => support
+                .fifo
+                .ok_or(unsupported("fifo", "default_priorities"))?(),
+            Self::Priority { capacity } => {
+                support
+                    .priority
+                    .ok_or(unsupported("priority", "hashable_items"))?(*capacity)
+            }
+            Self::Israeli {
+                capacity,
+                combiner,
+                inner,
+            } => {
+                let make = support.israeli.ok_or(unsupported("israeli", "friendly"))?;
+                let inner = inner
+                    .as_ref()
+                    .map(|inner| inner.build(support))
+                    .transpose()?;
+                make(*capacity, *combiner, inner.as_ref())
+            }
+            Self::RoundRobin {
+                capacity,
+                quantum,
+                combiner,
+            } => support
+                .round_robin
+                .ok_or(unsupported("round_robin", "friendly"))?(
+                *capacity, *quantum, *combiner
+            ),
+            Self::Radix {} => support.radix.ok_or(unsupported("radix", "radix_keys"))?(),
+            Self::Bucket {
+                coarse,
+                lower,
+                upper,
+                pool_limit,
+                inner,
+            } => {
+                let radix_key = support
+                    .radix_key
+                    .ok_or(unsupported("bucket", "radix_keys"))?;
+                let coarse = *coarse;
+                let mut to_return = BucketQueue::<
+                    T,
+                    P,
+                    u64,
+                    BoxedQueue<T, P>,
+                    HashMap<u64, BoxedQueue<T, P>>,
+                    BTreeSet<u64>,
+                    _,
+                >::with_coarse_grainer(
+                    *lower,
+                    *upper,
+                    &inner.build(support)?,
+                    move |p: &P| coarse.coarse_grain_key(radix_key(p)),
+                );
+                to_return.change_bucket_pool_limit(*pool_limit);
+                BoxedQueue::new(to_return)
+            }
+        })
+    }
+}
+
+mod test {
+    use crate::Friendly;
+
+    const MY_U8_FREINDLINESS: u8 = 5;
+
+    #[allow(dead_code)]
+    #[derive(PartialEq, Eq, Debug, Hash)]
+    #[repr(transparent)]
+    struct MyU8(u8);
+    impl Friendly<u8> for MyU8 {
+        fn friendship_shibboleth(&self) -> u8 {
+            self.0 % MY_U8_FREINDLINESS
+        }
+    }
+
+    #[test]
+    fn same_as_typed_builder() {
+        use super::{CoarseConfig, CombinerConfig, ConfigSupport, QueueConfig};
+        use crate::my_priority_queue::AbstractPriorityQueue;
+        use crate::{BucketQueueBuilder, DivideBy, IsraeliPriorityBuilder};
+        use std::num::NonZeroU64;
+
+        let config = QueueConfig::from_toml(
+            r#"
+            kind = "bucket"
+            coarse = { divide_by = 10 }
+            upper = 3
+            pool_limit = 2
+
+            [inner]
+            kind = "israeli"
+            capacity = 4
+            combiner = "keep_first"
+            "#,
+        )
+        .expect("valid config");
+        assert_eq!(
+            config,
+            QueueConfig::Bucket {
+                coarse: CoarseConfig::DivideBy(NonZeroU64::new(10).expect("nonzero")),
+                lower: 0,
+                upper: 3,
+                pool_limit: 2,
+                inner: Box::new(QueueConfig::Israeli {
+                    capacity: 4,
+                    combiner: CombinerConfig::KeepFirst,
+                    inner: None,
+                }),
+            }
+        );
+        let mut from_config = config
+            .build(&ConfigSupport::new().friendly::<u8>().radix_keys())
+            .expect("supported");
+        let mut typed = BucketQueueBuilder::<MyU8, u8, DivideBy<u8, 10>, _>::new(
+            IsraeliPriorityBuilder::<MyU8, u8, u8>::new()
+                .capacity(4)
+                .combiner(|p1, _| (false, *p1))
+                .build(),
+        )
+        .bounds(DivideBy(0), DivideBy(3))
+        .bucket_pool_limit(2)
+        .build();
+        let mut empty_copy = from_config.empty_copy();
+        for round in 0..3u8 {
+            for z in [3, 13, 8, 21, 26, 11, 16, 7] {
+                from_config.my_enqueue(MyU8(z), z + round);
+                typed.my_enqueue(MyU8(z), z + round);
+                empty_copy.my_enqueue(MyU8(z), z + round);
+            }
+            let expected = typed.dequeue_batch(5, 5);
+            assert_eq!(from_config.dequeue_batch(5, 5), expected);
+            assert_eq!(empty_copy.dequeue_batch(5, 5), expected);
+        }
+        assert_eq!(from_config.drain_all(), typed.drain_all());
+    }
+
+    #[test]
+    fn other_kinds() {
+        use super::{ConfigSupport, QueueConfig};
+        use crate::my_priority_queue::AbstractPriorityQueue;
+
+        let support = ConfigSupport::new()
+            .default_priorities()
+            .hashable_items()
+            .friendly::<u8>()
+            .radix_keys();
+        for (config, expected) in [
+            ("kind = \"fifo\"", vec![3, 9, 1, 7]),
+            ("kind = \"priority\"", vec![9, 7, 3, 1]),
+            ("kind = \"radix\"", vec![9, 7, 3, 1]),
+            (
+                "kind = \"round_robin\"\nquantum = 1\ncombiner = \"latest\"",
+                vec![9, 7, 3, 1],
+            ),
+            (
+                "kind = \"bucket\"\ncoarse = \"log2\"\n[inner]\nkind = \"fifo\"",
+                vec![9, 7, 3, 1],
+            ),
+            (
+                "kind = \"bucket\"\ncoarse = { top_bits = 61 }\ninner = { kind = \"fifo\" }",
+                vec![9, 3, 1, 7],
+            ),
+        ] {
+            let mut q = QueueConfig::from_toml(config)
+                .expect("valid config")
+                .build(&support)
+                .expect("supported");
+            for z in [3, 9, 1, 7] {
+                q.my_enqueue(MyU8(z), z);
+            }
+            let all_out: Vec<_> = q.drain_all().into_iter().map(|(z, _)| z.0).collect();
+            assert_eq!(all_out, expected, "{config}");
+        }
+        assert!(QueueConfig::from_toml("kind = \"nope\"").is_err());
+        assert!(QueueConfig::from_toml("kind = \"round_robin\"\nquantum = 0").is_err());
+        assert!(QueueConfig::from_toml(
+            "kind = \"bucket\"\ncoarse = { divide_by = 0 }\ninner = { kind = \"fifo\" }"
+        )
+        .is_err());
+        // misspelled or misplaced keys are not silently ignored
+        assert!(QueueConfig::from_toml("kind = \"israeli\"\ncombinr = \"latest\"").is_err());
+        assert!(QueueConfig::from_toml("kind = \"fifo\"\ncapacity = 4").is_err());
+    }
+
+    #[test]
+    fn israeli_of_buckets() {
+        use super::{ConfigSupport, QueueConfig};
+        use crate::my_priority_queue::AbstractPriorityQueue;
+
+        let mut q = QueueConfig::from_toml(
+            r#"
+            kind = "israeli"
+            combiner = "keep_first"
+
+            [inner]
+            kind = "bucket"
+            coarse = { divide_by = 10 }
+            inner = { kind = "priority" }
+            "#,
+        )
+        .expect("valid config")
+        .build(
+            &ConfigSupport::new()
+                .hashable_items()
+                .friendly::<u8>()
+                .radix_keys(),
+        )
+        .expect("supported");
+        for z in [3, 13, 8, 21, 26, 11, 16] {
+            q.my_enqueue(MyU8(z), z * 2);
+        }
+        let all_out: Vec<_> = q.drain_all().into_iter().map(|(z, _)| z.0).collect();
+        assert_eq!(all_out, vec![26, 21, 16, 11, 13, 8, 3]);
+    }
+
+    #[test]
+    fn only_what_is_supported() {
+        use super::{ConfigSupport, QueueConfig, Unsupported};
+        use crate::my_priority_queue::AbstractPriorityQueue;
+
+        /// can't be hashed and has no `RadixKey`
+        #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default, Debug)]
+        struct Level(u8);
+
+        let support = ConfigSupport::<MyU8, Level>::new()
+            .default_priorities()
+            .friendly::<u8>();
+        let mut q = QueueConfig::from_toml("kind = \"israeli\"\ninner = { kind = \"fifo\" }")
+            .expect("valid config")
+            .build(&support)
+            .expect("supported");
+        q.my_enqueue(MyU8(3), Level(1));
+        q.my_enqueue(MyU8(4), Level(2));
+        q.my_enqueue(MyU8(8), Level(0));
+        let all_out: Vec<_> = q.drain_all().into_iter().map(|(z, _)| z.0).collect();
+        assert_eq!(all_out, vec![4, 3, 8]);
+        for (config, kind, needs) in [
+            ("kind = \"priority\"", "priority", "hashable_items"),
+            ("kind = \"radix\"", "radix", "radix_keys"),
+            (
+                "kind = \"bucket\"\ncoarse = \"log2\"\ninner = { kind = \"fifo\" }",
+                "bucket",
+                "radix_keys",
+            ),
+        ] {
+            let built = QueueConfig::from_toml(config)
+                .expect("valid config")
+                .build(&support);
+            assert_eq!(built.err(), Some(Unsupported { kind, needs }), "{config}");
+        }
+    }
+}